@@ -72,6 +72,11 @@ impl MainWindow {
         let auto_label = version_label(WzVersion::AUTO_DETECT);
         let gms_label = version_label(WzVersion::GMS);
         let gms_old_label = version_label(WzVersion::GMS_OLD);
+        let kms_label = version_label(WzVersion::KMS);
+        let cms_label = version_label(WzVersion::CMS);
+        let tms_label = version_label(WzVersion::TMS);
+        let msea_label = version_label(WzVersion::MSEA);
+        let jms_label = version_label(WzVersion::JMS);
 
         ui.horizontal(|ui| {
             ui.menu_button("File", |ui| {
@@ -95,6 +100,11 @@ impl MainWindow {
                     ui.selectable_value(&mut self.wz_version, WzVersion::AUTO_DETECT, auto_label);
                     ui.selectable_value(&mut self.wz_version, WzVersion::GMS, gms_label);
                     ui.selectable_value(&mut self.wz_version, WzVersion::GMS_OLD, gms_old_label);
+                    ui.selectable_value(&mut self.wz_version, WzVersion::KMS, kms_label);
+                    ui.selectable_value(&mut self.wz_version, WzVersion::CMS, cms_label);
+                    ui.selectable_value(&mut self.wz_version, WzVersion::TMS, tms_label);
+                    ui.selectable_value(&mut self.wz_version, WzVersion::MSEA, msea_label);
+                    ui.selectable_value(&mut self.wz_version, WzVersion::JMS, jms_label);
                 });
         });
     }
@@ -235,6 +245,12 @@ pub fn version_label(wz_version: WzVersion) -> String {
         WzVersion::AUTO_DETECT => "Auto-detect".to_string(),
         WzVersion::GMS => "Modern".to_string(),
         WzVersion::GMS_OLD => "Legacy".to_string(),
+        WzVersion::KMS => "KMS".to_string(),
+        WzVersion::CMS => "CMS".to_string(),
+        WzVersion::TMS => "TMS".to_string(),
+        WzVersion::MSEA => "MSEA".to_string(),
+        WzVersion::JMS => "JMS".to_string(),
+        WzVersion::CUSTOM(iv) => format!("Custom ({:02X?})", iv),
     }
 }
 
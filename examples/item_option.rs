@@ -129,7 +129,7 @@ fn main() -> io::Result<()> {
 
         if write_to_file {
             let json_data = serde_json::to_string_pretty(&lookup_table).unwrap();
-            let mut file = File::create(&Path::new(output_file))?;
+            let mut file = File::create(Path::new(output_file))?;
             file.write_all(json_data.as_bytes())?;
         }
     }
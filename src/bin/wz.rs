@@ -0,0 +1,406 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    num::ParseIntError,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use wz::{
+    parse_canvas, resolve, safe_file_name, save_png, save_sound, to_json, walk_depth_first,
+    write_json_to_file, ArcWzNode, WzFile, WzReader, WzValue, WzValueQuery, WzVersion,
+};
+
+/// Inspect and extract MapleStory .wz archives
+#[derive(Parser)]
+#[command(name = "wz")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the header, patch version and key of an archive
+    Info {
+        #[command(flatten)]
+        file: FileArgs,
+    },
+    /// List the children of a node
+    Ls {
+        #[command(flatten)]
+        file: FileArgs,
+        /// Node path, e.g. `Consume/0200.img`. Defaults to the root
+        #[arg(default_value = "")]
+        path: String,
+    },
+    /// Print the value of a node
+    Cat {
+        #[command(flatten)]
+        file: FileArgs,
+        path: String,
+    },
+    /// Print a node and all of its descendants
+    Tree {
+        #[command(flatten)]
+        file: FileArgs,
+        #[arg(default_value = "")]
+        path: String,
+        /// Stop after this many levels
+        #[arg(short, long)]
+        depth: Option<usize>,
+    },
+    /// Write canvases as PNG, sounds and .img nodes as JSON into a directory
+    Extract {
+        #[command(flatten)]
+        file: FileArgs,
+        path: String,
+        dir: PathBuf,
+    },
+    /// Find every node whose name contains the query and whose value matches
+    Find {
+        #[command(flatten)]
+        file: FileArgs,
+        /// Part of the node name, case insensitive
+        query: Option<String>,
+        #[command(flatten)]
+        value: ValueArgs,
+    },
+    /// Print a node as JSON
+    Json {
+        #[command(flatten)]
+        file: FileArgs,
+        path: String,
+        /// Write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct FileArgs {
+    /// Path to the .wz file
+    file: String,
+    /// Encryption key of the client
+    #[arg(short, long, value_enum, default_value_t = KeyArg::Auto)]
+    key: KeyArg,
+    /// Custom IV as 8 hex digits, e.g. `4D23C72B`. Overrides --key
+    #[arg(long, value_parser = parse_iv)]
+    iv: Option<[u8; 4]>,
+    /// Patch version, skips the version detection
+    #[arg(short, long)]
+    patch: Option<i16>,
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct ValueArgs {
+    /// Exact string value, or the decimal form of an integer
+    #[arg(long)]
+    value: Option<String>,
+    /// Regex on string values and the decimal form of integers
+    #[arg(long, value_parser = Regex::new)]
+    regex: Option<Regex>,
+    /// Inclusive range of integer values, e.g. `100..200`
+    #[arg(long, value_parser = parse_range)]
+    range: Option<RangeInclusive<i64>>,
+}
+
+impl ValueArgs {
+    fn query(&self) -> Option<WzValueQuery> {
+        if let Some(value) = &self.value {
+            return Some(WzValueQuery::Literal(value.clone()));
+        }
+        if let Some(regex) = &self.regex {
+            return Some(WzValueQuery::Regex(regex.clone()));
+        }
+        self.range.clone().map(WzValueQuery::Range)
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum KeyArg {
+    Auto,
+    Gms,
+    GmsOld,
+    Kms,
+    Cms,
+    Tms,
+    Msea,
+    Jms,
+}
+
+impl From<KeyArg> for WzVersion {
+    fn from(key: KeyArg) -> Self {
+        match key {
+            KeyArg::Auto => WzVersion::AUTO_DETECT,
+            KeyArg::Gms => WzVersion::GMS,
+            KeyArg::GmsOld => WzVersion::GMS_OLD,
+            KeyArg::Kms => WzVersion::KMS,
+            KeyArg::Cms => WzVersion::CMS,
+            KeyArg::Tms => WzVersion::TMS,
+            KeyArg::Msea => WzVersion::MSEA,
+            KeyArg::Jms => WzVersion::JMS,
+        }
+    }
+}
+
+fn parse_range(value: &str) -> Result<RangeInclusive<i64>, String> {
+    let (start, end) = value
+        .split_once("..")
+        .ok_or_else(|| "expected a range like 100..200".to_string())?;
+    let end = end.trim_start_matches('=');
+    let start = start.parse().map_err(|e: ParseIntError| e.to_string())?;
+    let end = end.parse().map_err(|e: ParseIntError| e.to_string())?;
+    Ok(start..=end)
+}
+
+fn parse_iv(value: &str) -> Result<[u8; 4], String> {
+    let value = value.trim_start_matches("0x");
+    if value.len() != 8 {
+        return Err("expected 8 hex digits".to_string());
+    }
+
+    let mut iv = [0; 4];
+    for (i, byte) in iv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16).map_err(|e| e.to_string())?;
+    }
+
+    Ok(iv)
+}
+
+struct OpenedFile {
+    wz_file: WzFile,
+    root: ArcWzNode,
+}
+
+impl OpenedFile {
+    fn open(args: &FileArgs) -> io::Result<Self> {
+        let version = match args.iv {
+            Some(iv) => WzVersion::CUSTOM(iv),
+            None => args.key.into(),
+        };
+
+        let mut wz_file = WzFile::new(&args.file, version)?;
+        if let Some(patch) = args.patch {
+            wz_file.set_patch_version(patch);
+        }
+
+        wz_file.open()?;
+        let root = wz_file.parse_root_directory()?;
+
+        Ok(Self { wz_file, root })
+    }
+
+    fn resolve(&self, path: &str) -> io::Result<ArcWzNode> {
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Ok(self.root.clone());
+        }
+
+        resolve(&self.root, path)
+    }
+
+    fn reader(&self) -> Arc<WzReader> {
+        self.wz_file.reader.clone()
+    }
+}
+
+fn info(args: &FileArgs) -> io::Result<()> {
+    let opened = OpenedFile::open(args)?;
+    let wz_file = &opened.wz_file;
+    let header = &wz_file.header;
+
+    println!("file:          {}", wz_file.file_path.display());
+    println!("ident:         {}", header.ident);
+    println!("size:          {}", header.size);
+    println!("file start:    {}", header.file_start);
+    println!("copyright:     {}", header.copyright);
+    println!("size valid:    {}", wz_file.header_size_valid);
+    if let Some(encrypted_version) = header.encrypted_version {
+        println!("enc. version:  {}", encrypted_version);
+    }
+    println!("layout:        {:?}", wz_file.layout);
+    println!("version:       {}", wz_file.version);
+    println!("candidates:    {:?}", wz_file.version_candidates);
+    println!("version hash:  {}", wz_file.version_hash);
+    match wz_file.detected_version {
+        Some(version) => println!(
+            "key:           {:?} (IV {:02X?})",
+            version,
+            wz::get_iv_for_version(version)
+        ),
+        None => println!("key:           unknown"),
+    }
+    println!("entries:       {}", opened.root.children.len());
+
+    Ok(())
+}
+
+fn ls(args: &FileArgs, path: &str) -> io::Result<()> {
+    let opened = OpenedFile::open(args)?;
+    let node = opened.resolve(path)?;
+
+    for (name, child) in &node.children {
+        if child.children.is_empty() {
+            println!("{}\t{}", name, child.value);
+        } else {
+            println!("{}/", name);
+        }
+    }
+
+    Ok(())
+}
+
+fn cat(args: &FileArgs, path: &str) -> io::Result<()> {
+    let opened = OpenedFile::open(args)?;
+    let node = opened.resolve(path)?;
+
+    match &node.value {
+        WzValue::Short(val) => println!("{}", val),
+        WzValue::Int(val) => println!("{}", val),
+        WzValue::Long(val) => println!("{}", val),
+        WzValue::Float(val) => println!("{}", val),
+        WzValue::Double(val) => println!("{}", val),
+        WzValue::String(val) => println!("{}", val),
+        WzValue::Uol(val) => println!("{}", val),
+        WzValue::Vector(val) => println!("{}, {}", val.x, val.y),
+        value => println!("{}", value),
+    }
+
+    Ok(())
+}
+
+fn tree(args: &FileArgs, path: &str, depth: Option<usize>) -> io::Result<()> {
+    fn print_tree(node: &ArcWzNode, level: usize, depth: Option<usize>) {
+        let indent = "  ".repeat(level);
+        if node.children.is_empty() {
+            println!("{}{} = {}", indent, node.name, node.value);
+            return;
+        }
+
+        println!("{}{}/", indent, node.name);
+        if depth.is_some_and(|depth| level >= depth) {
+            return;
+        }
+
+        for child in node.children.values() {
+            print_tree(child, level + 1, depth);
+        }
+    }
+
+    let opened = OpenedFile::open(args)?;
+    let node = opened.resolve(path)?;
+    print_tree(&node, 0, depth);
+
+    Ok(())
+}
+
+fn extract(args: &FileArgs, path: &str, dir: &Path) -> io::Result<()> {
+    fn extract_node(node: &ArcWzNode, dir: &Path, reader: &Arc<WzReader>) -> io::Result<usize> {
+        let name = safe_file_name(&node.name);
+
+        match &node.value {
+            WzValue::Canvas(canvas) => {
+                fs::create_dir_all(dir)?;
+                let image = parse_canvas(canvas, reader.clone())?;
+                let file_path = dir.join(format!("{}.png", name));
+                save_png(&file_path.to_string_lossy(), &image)?;
+                return Ok(1);
+            }
+            WzValue::Sound(sound) => {
+                fs::create_dir_all(dir)?;
+                save_sound(&dir.to_string_lossy(), sound, reader.clone())?;
+                return Ok(1);
+            }
+            _ => {}
+        }
+
+        let mut count = 0;
+        if let WzValue::Img = node.value {
+            fs::create_dir_all(dir)?;
+            let file_path = dir.join(format!("{}.json", name));
+            write_json_to_file(&to_json(node)?, &file_path.to_string_lossy())?;
+            count += 1;
+        }
+
+        let child_dir = dir.join(&name);
+        for child in node.children.values() {
+            match extract_node(child, &child_dir, reader) {
+                Ok(child_count) => count += child_count,
+                Err(err) => log::warn!("failed to extract {}/{}: {}", node.name, child.name, err),
+            }
+        }
+
+        Ok(count)
+    }
+
+    let opened = OpenedFile::open(args)?;
+    let node = opened.resolve(path)?;
+    let count = extract_node(&node, dir, &opened.reader())?;
+    println!("extracted {} files to {}", count, dir.display());
+
+    Ok(())
+}
+
+fn find(args: &FileArgs, query: Option<&str>, value: &ValueArgs) -> io::Result<()> {
+    let value_query = value.query();
+    if query.is_none() && value_query.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Expected a name query or one of --value, --regex and --range",
+        ));
+    }
+
+    let opened = OpenedFile::open(args)?;
+    let query = query.map(str::to_lowercase);
+    for (path, node) in walk_depth_first(&opened.root) {
+        let name_matches = query
+            .as_ref()
+            .is_none_or(|query| node.name.to_lowercase().contains(query));
+        let value_matches = value_query
+            .as_ref()
+            .is_none_or(|value_query| value_query.matches(&node.value));
+        if name_matches && value_matches {
+            println!("{}", path);
+        }
+    }
+
+    Ok(())
+}
+
+fn json(args: &FileArgs, path: &str, output: Option<&str>) -> io::Result<()> {
+    let opened = OpenedFile::open(args)?;
+    let node = opened.resolve(path)?;
+    let json = to_json(&node)?;
+
+    match output {
+        Some(output) => write_json_to_file(&json, output),
+        None => {
+            println!("{}", json);
+            Ok(())
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    simple_logger::SimpleLogger::new()
+        .env()
+        .with_module_level("wz", log::LevelFilter::Warn)
+        .init()
+        .map_err(Error::other)?;
+
+    let cli = Cli::parse();
+
+    match &cli.command {
+        Command::Info { file } => info(file),
+        Command::Ls { file, path } => ls(file, path),
+        Command::Cat { file, path } => cat(file, path),
+        Command::Tree { file, path, depth } => tree(file, path, *depth),
+        Command::Extract { file, path, dir } => extract(file, path, dir),
+        Command::Find { file, query, value } => find(file, query.as_deref(), value),
+        Command::Json { file, path, output } => json(file, path, output.as_deref()),
+    }
+}
@@ -0,0 +1,511 @@
+use crate::{
+    draw_image, parse_canvas, resolve, resolve_uol_path, ArcWzNode, Vec2, WzImage, WzReader,
+    WzValue,
+};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+
+/// What to draw, ids as in the client, e.g. `1302000` for a sword
+#[derive(Debug, Clone)]
+pub struct Avatar {
+    /// 0 for the default skin, the body is `0000200<skin>.img`
+    pub skin: i32,
+    pub face: Option<i32>,
+    pub hair: Option<i32>,
+    pub equips: Vec<i32>,
+    pub stance: String,
+    pub frame: i32,
+    /// Face expression, e.g. `default` or `smile`
+    pub expression: String,
+}
+
+impl Default for Avatar {
+    fn default() -> Self {
+        Self {
+            skin: 0,
+            face: None,
+            hair: None,
+            equips: vec![],
+            stance: "stand1".to_string(),
+            frame: 0,
+            expression: "default".to_string(),
+        }
+    }
+}
+
+// A canvas of one item, positioned once its anchors are known
+struct AvatarPart {
+    item: usize,
+    image: WzImage,
+    z: String,
+    anchors: Vec<(String, Vec2)>,
+}
+
+// Items drawn on a character, with the slots they cover
+struct AvatarItem {
+    node: ArcWzNode,
+    path: String,
+    vslot: Vec<String>,
+}
+
+fn split_slots(slots: &str) -> Vec<String> {
+    slots
+        .as_bytes()
+        .chunks(2)
+        .map(|slot| String::from_utf8_lossy(slot).into_owned())
+        .collect()
+}
+
+// Items live in a directory named after their category, e.g. `Cap/01002000.img`
+fn find_item(character_wz: &ArcWzNode, id: i32) -> Option<(String, ArcWzNode)> {
+    let name = format!("{:08}.img", id);
+    if let Some(node) = character_wz.children.get(&name) {
+        return Some((name, node.clone()));
+    }
+
+    character_wz
+        .children
+        .iter()
+        .find_map(|(category, directory)| {
+            let node = directory.children.get(&name)?;
+            Some((format!("{}/{}", category, name), node.clone()))
+        })
+}
+
+/// Compose a character from Character.wz into a single image. Parts are placed by their
+/// `map` anchors (`navel`, `neck`, `hand`, `brow`, ...) starting from the body, stacked by
+/// their `z` layer in Base.wz `zmap.img`, and hidden when the slots their layer needs in
+/// `smap.img` are covered by another equip's `vslot`. The origin of the returned image is
+/// the origin of the body, at the feet.
+pub fn compose_avatar(
+    character_wz: &ArcWzNode,
+    base_wz: &ArcWzNode,
+    reader: Arc<WzReader>,
+    avatar: &Avatar,
+) -> Result<WzImage, Error> {
+    let zmap = resolve(base_wz, "zmap.img")?;
+    let smap = resolve(base_wz, "smap.img")?;
+
+    // zmap lists the layers from the front to the back
+    let layer_count = zmap.children.len();
+    let z_order = zmap
+        .children
+        .keys()
+        .enumerate()
+        .map(|(index, name)| (name.as_str(), layer_count - index))
+        .collect::<HashMap<_, _>>();
+
+    let mut item_ids = vec![2000 + avatar.skin, 12000 + avatar.skin];
+    item_ids.extend(avatar.face);
+    item_ids.extend(avatar.hair);
+    item_ids.extend(&avatar.equips);
+
+    let mut items = vec![];
+    for id in item_ids {
+        let Some((path, node)) = find_item(character_wz, id) else {
+            // Everything else is anchored to the body
+            if items.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("No body for skin {}", avatar.skin),
+                ));
+            }
+            log::warn!("avatar item {} not found", id);
+            continue;
+        };
+
+        let vslot = resolve(&node, "info/vslot")
+            .ok()
+            .and_then(|vslot| vslot.value.to_text())
+            .map(|vslot| split_slots(&vslot))
+            .unwrap_or_default();
+        items.push(AvatarItem { node, path, vslot });
+    }
+
+    // When two items cover the same slot, the one listed last wins
+    let mut slot_owners: HashMap<&String, usize> = HashMap::new();
+    for (index, item) in items.iter().enumerate() {
+        for slot in &item.vslot {
+            slot_owners.insert(slot, index);
+        }
+    }
+
+    let mut parts = vec![];
+    for (index, item) in items.iter().enumerate() {
+        // Faces are not animated by stance, but by expression
+        let is_face = item.path.starts_with("Face/");
+        let mut frame_path = if is_face {
+            match resolve(&item.node, &avatar.expression) {
+                Ok(expression) if expression.children.contains_key("0") => {
+                    format!("{}/0", avatar.expression)
+                }
+                _ => avatar.expression.clone(),
+            }
+        } else {
+            format!("{}/{}", avatar.stance, avatar.frame)
+        };
+
+        let Ok(mut frame) = resolve(&item.node, &frame_path) else {
+            continue;
+        };
+
+        // Frames shared between stances are UOLs to the frame, relative to its parent
+        if let WzValue::Uol(uol) = &frame.value {
+            let parent_path = frame_path.rsplit_once('/').map_or("", |(parent, _)| parent);
+            let resolved = resolve_uol_path(parent_path.to_string(), uol.clone())
+                .and_then(|path| Ok((resolve(&item.node, &path)?, path)));
+            let Ok((node, path)) = resolved else {
+                log::warn!("avatar frame {}/{} is a broken uol", item.path, frame_path);
+                continue;
+            };
+            frame = node;
+            frame_path = path;
+        }
+
+        for (name, part) in &frame.children {
+            let Some(part) = resolve_part(&item.node, &frame_path, part, avatar) else {
+                continue;
+            };
+            let WzValue::Canvas(canvas) = &part.value else {
+                continue;
+            };
+
+            let z = part
+                .children
+                .get("z")
+                .and_then(|z| z.value.to_text())
+                .unwrap_or_else(|| name.clone());
+
+            // Hidden when a slot the layer needs is covered by another item
+            if let Some(slots) = smap
+                .children
+                .get(&z)
+                .and_then(|slots| slots.value.to_text())
+            {
+                let covered = split_slots(&slots)
+                    .iter()
+                    .any(|slot| slot_owners.get(slot).is_some_and(|owner| *owner != index));
+                if covered {
+                    continue;
+                }
+            }
+
+            let anchors = part
+                .children
+                .get("map")
+                .map(|map| {
+                    map.children
+                        .iter()
+                        .filter_map(|(name, vector)| match &vector.value {
+                            WzValue::Vector(vector) => Some((name.clone(), vector.clone())),
+                            _ => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            parts.push(AvatarPart {
+                item: index,
+                image: parse_canvas(canvas, reader.clone())?,
+                z,
+                anchors,
+            });
+        }
+    }
+
+    // The body is drawn at the origin, every other part against an anchor that is already known
+    let Some(body) = parts.iter().position(|part| part.item == 0) else {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No body frame for {}/{}", avatar.stance, avatar.frame),
+        ));
+    };
+
+    let mut known_anchors: HashMap<String, Vec2> = parts[body].anchors.iter().cloned().collect();
+    let mut positions: Vec<Option<Vec2>> = vec![None; parts.len()];
+    positions[body] = Some(Vec2::default());
+
+    let mut placed_any = true;
+    while placed_any {
+        placed_any = false;
+        for (index, part) in parts.iter().enumerate() {
+            if positions[index].is_some() {
+                continue;
+            }
+
+            let Some(position) = part.anchors.iter().find_map(|(name, vector)| {
+                let anchor = known_anchors.get(name)?;
+                Some(Vec2 {
+                    x: anchor.x - vector.x,
+                    y: anchor.y - vector.y,
+                })
+            }) else {
+                continue;
+            };
+
+            for (name, vector) in &part.anchors {
+                known_anchors.entry(name.clone()).or_insert(Vec2 {
+                    x: position.x + vector.x,
+                    y: position.y + vector.y,
+                });
+            }
+            positions[index] = Some(position);
+            placed_any = true;
+        }
+    }
+
+    let mut placed = parts
+        .iter()
+        .zip(positions)
+        .filter_map(|(part, position)| match position {
+            Some(position) => Some((part, position)),
+            None => {
+                log::warn!("avatar part {} has no known anchor", part.z);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Unknown layers go to the back, ties keep the item order
+    placed.sort_by_key(|(part, _)| {
+        (
+            z_order.get(part.z.as_str()).copied().unwrap_or(0),
+            part.item,
+        )
+    });
+
+    // The body origin is at (0, 0), find the area covered by every part
+    let left = placed
+        .iter()
+        .map(|(part, position)| position.x - part.image.origin.x)
+        .min()
+        .unwrap_or(0);
+    let top = placed
+        .iter()
+        .map(|(part, position)| position.y - part.image.origin.y)
+        .min()
+        .unwrap_or(0);
+    let right = placed
+        .iter()
+        .map(|(part, position)| position.x - part.image.origin.x + part.image.width as i32)
+        .max()
+        .unwrap_or(0);
+    let bottom = placed
+        .iter()
+        .map(|(part, position)| position.y - part.image.origin.y + part.image.height as i32)
+        .max()
+        .unwrap_or(0);
+
+    let width = (right - left).max(0) as u32;
+    let height = (bottom - top).max(0) as u32;
+    let mut image = WzImage {
+        width,
+        height,
+        origin: Vec2 { x: -left, y: -top },
+        data: vec![0; (width * height * 4) as usize],
+    };
+
+    for (part, position) in placed {
+        draw_image(
+            &mut image,
+            &part.image,
+            position.x - left,
+            position.y - top,
+            false,
+            255,
+        );
+    }
+
+    Ok(image)
+}
+
+// Follow UOLs, relative to the frame, and pick the variant for the skin of parts like `hairShade`
+fn resolve_part(
+    item: &ArcWzNode,
+    frame_path: &str,
+    part: &ArcWzNode,
+    avatar: &Avatar,
+) -> Option<ArcWzNode> {
+    match &part.value {
+        WzValue::Canvas(_) => Some(part.clone()),
+        WzValue::Uol(uol) => {
+            let path = resolve_uol_path(frame_path.to_string(), uol.clone()).ok()?;
+            let node = resolve(item, &path).ok()?;
+            matches!(node.value, WzValue::Canvas(_)).then_some(node)
+        }
+        _ => {
+            let variant = part
+                .children
+                .get(&avatar.skin.to_string())
+                .or_else(|| part.children.get("0"))?;
+            matches!(variant.value, WzValue::Canvas(_)).then(|| variant.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode_canvas,
+        test_tree::{leaf, node},
+        write_canvas_payload, WzCanvasQuality, WzReader,
+    };
+    use std::io::Cursor;
+
+    // A solid canvas with its layer and anchors
+    fn part(
+        payloads: &mut Vec<u8>,
+        name: &str,
+        size: u32,
+        origin: Vec2,
+        color: [u8; 4],
+        anchors: &[(&str, Vec2)],
+    ) -> ArcWzNode {
+        let image = WzImage {
+            width: size,
+            height: size,
+            origin,
+            data: color.repeat((size * size) as usize),
+        };
+        let (mut canvas, payload) = encode_canvas(&image, 2, WzCanvasQuality::Fast).unwrap();
+        canvas.offset = write_canvas_payload(payloads, &payload);
+
+        let anchors = anchors
+            .iter()
+            .map(|(name, vector)| leaf(name, WzValue::Vector(vector.clone())))
+            .collect();
+        node(
+            name,
+            WzValue::Canvas(canvas),
+            vec![
+                leaf("z", WzValue::String(name.to_string())),
+                node("map", WzValue::Extended, anchors),
+            ],
+        )
+    }
+
+    fn stance(name: &str, frame: ArcWzNode) -> ArcWzNode {
+        node(
+            name,
+            WzValue::Extended,
+            vec![frame, leaf("1", WzValue::Uol("0".to_string()))],
+        )
+    }
+
+    fn wz_files() -> (ArcWzNode, ArcWzNode, Arc<WzReader>) {
+        let mut payloads = vec![];
+        let red = [255, 0, 0, 255];
+        let body = node(
+            "00002000.img",
+            WzValue::Img,
+            vec![stance(
+                "stand1",
+                node(
+                    "0",
+                    WzValue::Extended,
+                    vec![
+                        part(
+                            &mut payloads,
+                            "body",
+                            4,
+                            Vec2 { x: 2, y: 4 },
+                            red,
+                            &[("navel", Vec2 { x: 0, y: -2 })],
+                        ),
+                        part(
+                            &mut payloads,
+                            "arm",
+                            2,
+                            Vec2::default(),
+                            [0, 255, 0, 255],
+                            &[("navel", Vec2 { x: 1, y: 1 })],
+                        ),
+                    ],
+                ),
+            )],
+        );
+        let head = node("00012000.img", WzValue::Img, vec![]);
+
+        // Only anchored to the brow, which nothing else has
+        let cap_frame = |payloads: &mut Vec<u8>| {
+            node(
+                "0",
+                WzValue::Extended,
+                vec![part(
+                    payloads,
+                    "cap",
+                    8,
+                    Vec2::default(),
+                    red,
+                    &[("brow", Vec2::default())],
+                )],
+            )
+        };
+        let cap = node(
+            "01002000.img",
+            WzValue::Img,
+            vec![
+                stance("stand1", cap_frame(&mut payloads)),
+                stance("walk1", cap_frame(&mut payloads)),
+            ],
+        );
+
+        let character_wz = node(
+            "Character.wz",
+            WzValue::Directory,
+            vec![body, head, node("Cap", WzValue::Directory, vec![cap])],
+        );
+        let base_wz = node(
+            "Base.wz",
+            WzValue::Directory,
+            vec![
+                node(
+                    "zmap.img",
+                    WzValue::Img,
+                    ["cap", "arm", "body"]
+                        .map(|name| leaf(name, WzValue::Null))
+                        .to_vec(),
+                ),
+                node("smap.img", WzValue::Img, vec![]),
+            ],
+        );
+        let reader = WzReader::new(Cursor::new(payloads), None);
+
+        (character_wz, base_wz, reader.into())
+    }
+
+    #[test]
+    fn test_compose_avatar() {
+        let (character_wz, base_wz, reader) = wz_files();
+        let avatar = Avatar {
+            equips: vec![1002000],
+            // A uol to frame 0
+            frame: 1,
+            ..Default::default()
+        };
+
+        // The cap has no known anchor, so it is left out
+        let image = compose_avatar(&character_wz, &base_wz, reader.clone(), &avatar).unwrap();
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!((image.origin.x, image.origin.y), (2, 4));
+
+        // The arm is placed at the body navel, (0, -2) from the origin
+        let pixel = |x: u32, y: u32| &image.data[((y * 4 + x) * 4) as usize..][..4];
+        assert_eq!(pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(1, 1), [0, 255, 0, 255]);
+        assert_eq!(pixel(2, 2), [0, 255, 0, 255]);
+        assert_eq!(pixel(3, 3), [255, 0, 0, 255]);
+
+        // Nothing is drawn at the origin in place of a missing body frame
+        let walking = Avatar {
+            stance: "walk1".to_string(),
+            ..avatar
+        };
+        assert!(compose_avatar(&character_wz, &base_wz, reader, &walking)
+            .is_err_and(|err| err.kind() == ErrorKind::NotFound));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod avatar;
+
+pub use avatar::*;
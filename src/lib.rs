@@ -1,7 +1,15 @@
+pub mod character;
+pub mod maps;
+pub mod nx;
 pub mod properties;
+pub mod strings;
 pub mod util;
 pub mod wz_file;
 
+pub use character::*;
+pub use maps::*;
+pub use nx::*;
 pub use properties::*;
+pub use strings::*;
 pub use util::*;
 pub use wz_file::*;
@@ -0,0 +1,459 @@
+use crate::{resolve, ArcWzNode, WzNode};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+
+/// Portals with this target map lead nowhere
+pub const NO_TARGET_MAP: i32 = 999999999;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MapBounds {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl MapBounds {
+    pub fn width(&self) -> i32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> i32 {
+        self.bottom - self.top
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct MapInfo {
+    pub bgm: String,
+    pub return_map: Option<i32>,
+    pub forced_return: Option<i32>,
+    pub field_limit: i32,
+    pub mob_rate: f64,
+    pub town: bool,
+    pub swim: bool,
+    pub fly: bool,
+    pub map_mark: String,
+    /// Map whose layout this map reuses
+    pub link: Option<i32>,
+    pub time_limit: Option<i32>,
+    /// `VRLeft`, `VRTop`, `VRRight` and `VRBottom`, when the map sets them
+    pub vr: Option<MapBounds>,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct Foothold {
+    pub id: i32,
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+    pub prev: i32,
+    pub next: i32,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct Portal {
+    pub id: i32,
+    pub name: String,
+    pub portal_type: i32,
+    pub x: i32,
+    pub y: i32,
+    pub target_map: Option<i32>,
+    pub target_portal: String,
+    pub script: String,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct LadderRope {
+    pub id: i32,
+    /// Ladder, otherwise a rope
+    pub ladder: bool,
+    /// Can be climbed from the foothold above
+    pub upper_foothold: bool,
+    pub x: i32,
+    pub y1: i32,
+    pub y2: i32,
+    pub page: i32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifeType {
+    #[default]
+    Mob,
+    Npc,
+    Other(String),
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct Life {
+    /// Mob or npc id, life without one is skipped
+    pub id: i32,
+    pub life_type: LifeType,
+    pub x: i32,
+    pub y: i32,
+    pub cy: i32,
+    pub foothold: i32,
+    /// Horizontal range the life moves within
+    pub rx0: i32,
+    pub rx1: i32,
+    /// Respawn time in seconds
+    pub mob_time: i32,
+    pub flip: bool,
+    pub hide: bool,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct Reactor {
+    /// Reactor id, reactors without one are skipped
+    pub id: i32,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub reactor_time: i32,
+    pub flip: bool,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct Background {
+    pub id: i32,
+    /// `bS`, the .img under Map/Back
+    pub back_set: String,
+    pub no: i32,
+    /// 0 for a still back, 1 for an animated one, 2 for spine
+    pub ani: i32,
+    /// Tiling and scrolling mode
+    pub back_type: i32,
+    pub x: i32,
+    pub y: i32,
+    /// Parallax
+    pub rx: i32,
+    pub ry: i32,
+    /// Tiling distance, 0 uses the sprite size
+    pub cx: i32,
+    pub cy: i32,
+    pub alpha: i32,
+    pub front: bool,
+    pub flip: bool,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct Tile {
+    pub id: i32,
+    /// `tS` of the layer, the .img under Map/Tile
+    pub tile_set: String,
+    /// Tile kind, e.g. `bsc` or `edU`
+    pub u: String,
+    pub no: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z_mass: i32,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct MapObject {
+    pub id: i32,
+    /// `oS`, the .img under Map/Obj, followed by the `l0/l1/l2` path inside it
+    pub object_set: String,
+    pub l0: String,
+    pub l1: String,
+    pub l2: String,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub z_mass: i32,
+    pub flip: bool,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct MapLayer {
+    pub index: i32,
+    pub tile_set: String,
+    pub tiles: Vec<Tile>,
+    pub objects: Vec<MapObject>,
+}
+
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct Map {
+    pub id: i32,
+    pub info: MapInfo,
+    /// By layer, then by group
+    pub footholds: IndexMap<i32, IndexMap<i32, Vec<Foothold>>>,
+    pub portals: Vec<Portal>,
+    pub ladder_ropes: Vec<LadderRope>,
+    pub life: Vec<Life>,
+    pub reactors: Vec<Reactor>,
+    pub backgrounds: Vec<Background>,
+    /// Layers 0 to 7, drawn in order
+    pub layers: Vec<MapLayer>,
+}
+
+impl Map {
+    pub fn mobs(&self) -> impl Iterator<Item = &Life> {
+        self.life
+            .iter()
+            .filter(|life| life.life_type == LifeType::Mob)
+    }
+
+    pub fn npcs(&self) -> impl Iterator<Item = &Life> {
+        self.life
+            .iter()
+            .filter(|life| life.life_type == LifeType::Npc)
+    }
+
+    pub fn all_footholds(&self) -> impl Iterator<Item = &Foothold> {
+        self.footholds
+            .values()
+            .flat_map(|groups| groups.values().flatten())
+    }
+}
+
+fn get_i32(node: &WzNode, name: &str) -> Option<i32> {
+    node.children.get(name)?.value.to_i32()
+}
+
+fn get_f64(node: &WzNode, name: &str) -> Option<f64> {
+    node.children.get(name)?.value.to_f64()
+}
+
+fn get_text(node: &WzNode, name: &str) -> String {
+    node.children
+        .get(name)
+        .and_then(|child| child.value.to_text())
+        .unwrap_or_default()
+}
+
+fn get_bool(node: &WzNode, name: &str) -> bool {
+    get_i32(node, name).is_some_and(|val| val != 0)
+}
+
+// Children named by an index, e.g. the entries of `portal`
+fn indexed_children<'a>(
+    node: &'a ArcWzNode,
+    path: &str,
+) -> impl Iterator<Item = (i32, &'a ArcWzNode)> {
+    let parent = if path.is_empty() {
+        Some(node)
+    } else {
+        path.split('/')
+            .try_fold(node, |node, name| node.children.get(name))
+    };
+
+    parent.into_iter().flat_map(|parent| {
+        parent
+            .children
+            .iter()
+            .filter_map(|(name, child)| Some((name.parse().ok()?, child)))
+    })
+}
+
+/// Build a map from a `Map/MapN/<id>.img` node
+pub fn parse_map(node: &ArcWzNode) -> Result<Map, Error> {
+    let info_node = resolve(node, "info")
+        .map_err(|_| Error::new(ErrorKind::NotFound, format!("{} has no info", node.name)))?;
+
+    let vr = match (
+        get_i32(&info_node, "VRLeft"),
+        get_i32(&info_node, "VRTop"),
+        get_i32(&info_node, "VRRight"),
+        get_i32(&info_node, "VRBottom"),
+    ) {
+        (Some(left), Some(top), Some(right), Some(bottom)) => Some(MapBounds {
+            left,
+            top,
+            right,
+            bottom,
+        }),
+        _ => None,
+    };
+
+    let valid_map = |map: Option<i32>| map.filter(|map| *map != NO_TARGET_MAP);
+    let info = MapInfo {
+        bgm: get_text(&info_node, "bgm"),
+        return_map: valid_map(get_i32(&info_node, "returnMap")),
+        forced_return: valid_map(get_i32(&info_node, "forcedReturn")),
+        field_limit: get_i32(&info_node, "fieldLimit").unwrap_or_default(),
+        mob_rate: get_f64(&info_node, "mobRate").unwrap_or(1.0),
+        town: get_bool(&info_node, "town"),
+        swim: get_bool(&info_node, "swim"),
+        fly: get_bool(&info_node, "fly"),
+        map_mark: get_text(&info_node, "mapMark"),
+        link: get_i32(&info_node, "link"),
+        time_limit: get_i32(&info_node, "timeLimit"),
+        vr,
+    };
+
+    let mut footholds = IndexMap::new();
+    for (layer, layer_node) in indexed_children(node, "foothold") {
+        let groups: &mut IndexMap<i32, Vec<Foothold>> = footholds.entry(layer).or_default();
+        for (group, group_node) in indexed_children(layer_node, "") {
+            let group_footholds = groups.entry(group).or_default();
+            for (id, foothold) in indexed_children(group_node, "") {
+                group_footholds.push(Foothold {
+                    id,
+                    x1: get_i32(foothold, "x1").unwrap_or_default(),
+                    y1: get_i32(foothold, "y1").unwrap_or_default(),
+                    x2: get_i32(foothold, "x2").unwrap_or_default(),
+                    y2: get_i32(foothold, "y2").unwrap_or_default(),
+                    prev: get_i32(foothold, "prev").unwrap_or_default(),
+                    next: get_i32(foothold, "next").unwrap_or_default(),
+                });
+            }
+        }
+    }
+
+    let portals = indexed_children(node, "portal")
+        .map(|(id, portal)| Portal {
+            id,
+            name: get_text(portal, "pn"),
+            portal_type: get_i32(portal, "pt").unwrap_or_default(),
+            x: get_i32(portal, "x").unwrap_or_default(),
+            y: get_i32(portal, "y").unwrap_or_default(),
+            target_map: valid_map(get_i32(portal, "tm")),
+            target_portal: get_text(portal, "tn"),
+            script: get_text(portal, "script"),
+        })
+        .collect();
+
+    let ladder_ropes = indexed_children(node, "ladderRope")
+        .map(|(id, ladder_rope)| LadderRope {
+            id,
+            ladder: get_bool(ladder_rope, "l"),
+            upper_foothold: get_bool(ladder_rope, "uf"),
+            x: get_i32(ladder_rope, "x").unwrap_or_default(),
+            y1: get_i32(ladder_rope, "y1").unwrap_or_default(),
+            y2: get_i32(ladder_rope, "y2").unwrap_or_default(),
+            page: get_i32(ladder_rope, "page").unwrap_or_default(),
+        })
+        .collect();
+
+    let life = indexed_children(node, "life")
+        .filter_map(|(index, life)| {
+            let Some(id) = get_i32(life, "id") else {
+                log::warn!("{}: skipping life/{} without an id", node.name, index);
+                return None;
+            };
+            Some((id, life))
+        })
+        .map(|(id, life)| Life {
+            id,
+            life_type: match get_text(life, "type").as_str() {
+                "m" => LifeType::Mob,
+                "n" => LifeType::Npc,
+                other => LifeType::Other(other.to_string()),
+            },
+            x: get_i32(life, "x").unwrap_or_default(),
+            y: get_i32(life, "y").unwrap_or_default(),
+            cy: get_i32(life, "cy").unwrap_or_default(),
+            foothold: get_i32(life, "fh").unwrap_or_default(),
+            rx0: get_i32(life, "rx0").unwrap_or_default(),
+            rx1: get_i32(life, "rx1").unwrap_or_default(),
+            mob_time: get_i32(life, "mobTime").unwrap_or_default(),
+            flip: get_bool(life, "f"),
+            hide: get_bool(life, "hide"),
+        })
+        .collect();
+
+    let reactors = indexed_children(node, "reactor")
+        .filter_map(|(index, reactor)| {
+            let Some(id) = get_i32(reactor, "id") else {
+                log::warn!("{}: skipping reactor/{} without an id", node.name, index);
+                return None;
+            };
+            Some((id, reactor))
+        })
+        .map(|(id, reactor)| Reactor {
+            id,
+            name: get_text(reactor, "name"),
+            x: get_i32(reactor, "x").unwrap_or_default(),
+            y: get_i32(reactor, "y").unwrap_or_default(),
+            reactor_time: get_i32(reactor, "reactorTime").unwrap_or_default(),
+            flip: get_bool(reactor, "f"),
+        })
+        .collect();
+
+    let backgrounds = indexed_children(node, "back")
+        .map(|(id, back)| Background {
+            id,
+            back_set: get_text(back, "bS"),
+            no: get_i32(back, "no").unwrap_or_default(),
+            ani: get_i32(back, "ani").unwrap_or_default(),
+            back_type: get_i32(back, "type").unwrap_or_default(),
+            x: get_i32(back, "x").unwrap_or_default(),
+            y: get_i32(back, "y").unwrap_or_default(),
+            rx: get_i32(back, "rx").unwrap_or_default(),
+            ry: get_i32(back, "ry").unwrap_or_default(),
+            cx: get_i32(back, "cx").unwrap_or_default(),
+            cy: get_i32(back, "cy").unwrap_or_default(),
+            alpha: get_i32(back, "a").unwrap_or(255),
+            front: get_bool(back, "front"),
+            flip: get_bool(back, "f"),
+        })
+        .collect();
+
+    let mut layers = indexed_children(node, "")
+        .filter(|(_, layer)| {
+            layer.children.contains_key("tile") || layer.children.contains_key("obj")
+        })
+        .map(|(index, layer)| {
+            let tile_set = layer
+                .children
+                .get("info")
+                .map(|info| get_text(info, "tS"))
+                .unwrap_or_default();
+
+            let tiles = indexed_children(layer, "tile")
+                .map(|(id, tile)| Tile {
+                    id,
+                    tile_set: tile_set.clone(),
+                    u: get_text(tile, "u"),
+                    no: get_i32(tile, "no").unwrap_or_default(),
+                    x: get_i32(tile, "x").unwrap_or_default(),
+                    y: get_i32(tile, "y").unwrap_or_default(),
+                    z_mass: get_i32(tile, "zM").unwrap_or_default(),
+                })
+                .collect();
+
+            let objects = indexed_children(layer, "obj")
+                .map(|(id, object)| MapObject {
+                    id,
+                    object_set: get_text(object, "oS"),
+                    l0: get_text(object, "l0"),
+                    l1: get_text(object, "l1"),
+                    l2: get_text(object, "l2"),
+                    x: get_i32(object, "x").unwrap_or_default(),
+                    y: get_i32(object, "y").unwrap_or_default(),
+                    z: get_i32(object, "z").unwrap_or_default(),
+                    z_mass: get_i32(object, "zM").unwrap_or_default(),
+                    flip: get_bool(object, "f"),
+                })
+                .collect();
+
+            MapLayer {
+                index,
+                tile_set,
+                tiles,
+                objects,
+            }
+        })
+        .collect::<Vec<_>>();
+    layers.sort_by_key(|layer| layer.index);
+
+    Ok(Map {
+        id: node
+            .name
+            .trim_end_matches(".img")
+            .parse()
+            .unwrap_or_default(),
+        info,
+        footholds,
+        portals,
+        ladder_ropes,
+        life,
+        reactors,
+        backgrounds,
+        layers,
+    })
+}
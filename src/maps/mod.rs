@@ -0,0 +1,5 @@
+pub mod map;
+pub mod renderer;
+
+pub use map::*;
+pub use renderer::*;
@@ -0,0 +1,358 @@
+use crate::{
+    blend_pixel, draw_image, parse_canvas, resolve, resolve_uol_path, save_png, ArcWzNode,
+    LifeType, Map, MapBounds, WzImage, WzReader, WzValue,
+};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct MapRenderOptions {
+    pub backgrounds: bool,
+    /// Debug overlays
+    pub footholds: bool,
+    pub ladder_ropes: bool,
+    pub portals: bool,
+    pub spawn_points: bool,
+}
+
+impl Default for MapRenderOptions {
+    fn default() -> Self {
+        Self {
+            backgrounds: true,
+            footholds: false,
+            ladder_ropes: false,
+            portals: false,
+            spawn_points: false,
+        }
+    }
+}
+
+const FOOTHOLD_COLOR: [u8; 4] = [255, 0, 0, 255];
+const LADDER_ROPE_COLOR: [u8; 4] = [255, 200, 0, 255];
+const PORTAL_COLOR: [u8; 4] = [0, 255, 0, 255];
+const MOB_COLOR: [u8; 4] = [255, 0, 255, 255];
+const NPC_COLOR: [u8; 4] = [0, 128, 255, 255];
+
+// Maps without VR bounds are framed around their footholds
+const FOOTHOLD_MARGIN: i32 = 100;
+
+/// The area a map is drawn in: its VR bounds, or the footholds with a margin
+pub fn get_map_bounds(map: &Map) -> MapBounds {
+    if let Some(vr) = map.info.vr {
+        return vr;
+    }
+
+    let mut footholds = map.all_footholds().peekable();
+    if footholds.peek().is_none() {
+        return MapBounds::default();
+    }
+
+    let mut bounds = MapBounds {
+        left: i32::MAX,
+        top: i32::MAX,
+        right: i32::MIN,
+        bottom: i32::MIN,
+    };
+    for foothold in footholds {
+        bounds.left = bounds.left.min(foothold.x1.min(foothold.x2));
+        bounds.right = bounds.right.max(foothold.x1.max(foothold.x2));
+        bounds.top = bounds.top.min(foothold.y1.min(foothold.y2));
+        bounds.bottom = bounds.bottom.max(foothold.y1.max(foothold.y2));
+    }
+
+    MapBounds {
+        left: bounds.left - FOOTHOLD_MARGIN,
+        top: bounds.top - FOOTHOLD_MARGIN,
+        right: bounds.right + FOOTHOLD_MARGIN,
+        bottom: bounds.bottom + FOOTHOLD_MARGIN,
+    }
+}
+
+// Decodes each sprite once, maps repeat the same tiles a lot
+struct SpriteCache<'a> {
+    map_wz: &'a ArcWzNode,
+    reader: Arc<WzReader>,
+    sprites: HashMap<String, Option<Arc<WzImage>>>,
+}
+
+impl SpriteCache<'_> {
+    fn get(&mut self, path: &str) -> Option<Arc<WzImage>> {
+        if let Some(sprite) = self.sprites.get(path) {
+            return sprite.clone();
+        }
+
+        let sprite = match self.load(path) {
+            Ok(sprite) => Some(Arc::new(sprite)),
+            Err(err) => {
+                log::warn!("failed to load map sprite {}: {}", path, err);
+                None
+            }
+        };
+        self.sprites.insert(path.to_string(), sprite.clone());
+        sprite
+    }
+
+    fn load(&self, path: &str) -> Result<WzImage, Error> {
+        let mut path = path.to_string();
+        let mut node = self.resolve_sprite(&mut path)?;
+
+        // Animated sprites are drawn with their first frame
+        if !matches!(node.value, WzValue::Canvas(_)) {
+            path = format!("{}/0", path);
+            node = self.resolve_sprite(&mut path)?;
+        }
+
+        match &node.value {
+            WzValue::Canvas(canvas) => parse_canvas(canvas, self.reader.clone()),
+            value => Err(Error::other(format!("not a canvas: {}", value))),
+        }
+    }
+
+    // Follows a UOL, relative to its parent, and updates the path to the node it points to
+    fn resolve_sprite(&self, path: &mut String) -> Result<ArcWzNode, Error> {
+        let node = resolve(self.map_wz, path)?;
+        let WzValue::Uol(uol) = &node.value else {
+            return Ok(node);
+        };
+
+        let parent_path = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        *path = resolve_uol_path(parent_path.to_string(), uol.clone())?;
+        resolve(self.map_wz, path)
+    }
+}
+
+/// Draw a map onto an RGBA image of its bounds: backgrounds, then each layer's objects and
+/// tiles in z order, then front backgrounds and the enabled overlays. `map_wz` is the root of
+/// Map.wz, where the `Back`, `Obj` and `Tile` sprites are resolved.
+pub fn render_map(
+    map: &Map,
+    map_wz: &ArcWzNode,
+    reader: Arc<WzReader>,
+    options: &MapRenderOptions,
+) -> Result<WzImage, Error> {
+    let bounds = get_map_bounds(map);
+    // Bounds come from the file, so the size is computed without overflowing
+    let width = bounds.right as i64 - bounds.left as i64;
+    let height = bounds.bottom as i64 - bounds.top as i64;
+    if width <= 0 || height <= 0 {
+        return Err(Error::other(format!("Map {} has no area to draw", map.id)));
+    }
+
+    let (width, height, size) = u32::try_from(width)
+        .ok()
+        .zip(u32::try_from(height).ok())
+        .and_then(|(width, height)| {
+            let size = (width as usize)
+                .checked_mul(height as usize)?
+                .checked_mul(4)?;
+            Some((width, height, size))
+        })
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Map {} is too large to draw ({}x{})", map.id, width, height),
+            )
+        })?;
+
+    let mut canvas = WzImage {
+        width,
+        height,
+        data: vec![0; size],
+        ..Default::default()
+    };
+    let mut sprites = SpriteCache {
+        map_wz,
+        reader,
+        sprites: HashMap::new(),
+    };
+
+    if options.backgrounds {
+        draw_backgrounds(&mut canvas, &bounds, map, &mut sprites, false);
+    }
+
+    for layer in &map.layers {
+        let mut objects = layer.objects.iter().collect::<Vec<_>>();
+        objects.sort_by_key(|object| (object.z, object.id));
+        for object in objects {
+            let path = format!(
+                "Obj/{}.img/{}/{}/{}",
+                object.object_set, object.l0, object.l1, object.l2
+            );
+            if let Some(sprite) = sprites.get(&path) {
+                let x = object.x - bounds.left;
+                let y = object.y - bounds.top;
+                draw_image(&mut canvas, &sprite, x, y, object.flip, 255);
+            }
+        }
+
+        let mut tiles = layer.tiles.iter().collect::<Vec<_>>();
+        tiles.sort_by_key(|tile| (tile.z_mass, tile.id));
+        for tile in tiles {
+            let path = format!("Tile/{}.img/{}/{}", tile.tile_set, tile.u, tile.no);
+            if let Some(sprite) = sprites.get(&path) {
+                let x = tile.x - bounds.left;
+                let y = tile.y - bounds.top;
+                draw_image(&mut canvas, &sprite, x, y, false, 255);
+            }
+        }
+    }
+
+    if options.backgrounds {
+        draw_backgrounds(&mut canvas, &bounds, map, &mut sprites, true);
+    }
+
+    if options.footholds {
+        for foothold in map.all_footholds() {
+            draw_line(
+                &mut canvas,
+                (foothold.x1 - bounds.left, foothold.y1 - bounds.top),
+                (foothold.x2 - bounds.left, foothold.y2 - bounds.top),
+                FOOTHOLD_COLOR,
+            );
+        }
+    }
+
+    if options.ladder_ropes {
+        for ladder_rope in &map.ladder_ropes {
+            let x = ladder_rope.x - bounds.left;
+            draw_line(
+                &mut canvas,
+                (x, ladder_rope.y1 - bounds.top),
+                (x, ladder_rope.y2 - bounds.top),
+                LADDER_ROPE_COLOR,
+            );
+        }
+    }
+
+    if options.portals {
+        for portal in &map.portals {
+            let (x, y) = (portal.x - bounds.left, portal.y - bounds.top);
+            draw_rect(&mut canvas, x - 10, y - 20, 20, 20, PORTAL_COLOR);
+        }
+    }
+
+    if options.spawn_points {
+        for life in &map.life {
+            let color = match life.life_type {
+                LifeType::Npc => NPC_COLOR,
+                _ => MOB_COLOR,
+            };
+            let (x, y) = (life.x - bounds.left, life.cy - bounds.top);
+            draw_rect(&mut canvas, x - 4, y - 8, 8, 8, color);
+        }
+    }
+
+    Ok(canvas)
+}
+
+fn draw_backgrounds(
+    canvas: &mut WzImage,
+    bounds: &MapBounds,
+    map: &Map,
+    sprites: &mut SpriteCache,
+    front: bool,
+) {
+    for back in map.backgrounds.iter().filter(|back| back.front == front) {
+        if back.back_set.is_empty() {
+            continue;
+        }
+
+        let path = match back.ani {
+            0 => format!("Back/{}.img/back/{}", back.back_set, back.no),
+            1 => format!("Back/{}.img/ani/{}", back.back_set, back.no),
+            // Spine backgrounds are not canvases
+            _ => continue,
+        };
+        let Some(sprite) = sprites.get(&path) else {
+            continue;
+        };
+
+        // Types 1, 3, 4, 6 and 7 tile horizontally, 2, 3, 5, 6 and 7 vertically
+        let tile_x = matches!(back.back_type, 1 | 3 | 4 | 6 | 7);
+        let tile_y = matches!(back.back_type, 2 | 3 | 5 | 6 | 7);
+        let step_x = if back.cx > 0 {
+            back.cx
+        } else {
+            sprite.width as i32
+        };
+        let step_y = if back.cy > 0 {
+            back.cy
+        } else {
+            sprite.height as i32
+        };
+
+        let x = back.x - bounds.left;
+        let y = back.y - bounds.top;
+        let xs = tile_positions(x, step_x, canvas.width as i32, tile_x);
+        let ys = tile_positions(y, step_y, canvas.height as i32, tile_y);
+
+        let alpha = back.alpha.clamp(0, 255) as u8;
+        for y in &ys {
+            for x in &xs {
+                draw_image(canvas, &sprite, *x, *y, back.flip, alpha);
+            }
+        }
+    }
+}
+
+// Every position of a repeated sprite that covers 0..len, or only the start when not tiled
+fn tile_positions(start: i32, step: i32, len: i32, tiled: bool) -> Vec<i32> {
+    if !tiled || step <= 0 {
+        return vec![start];
+    }
+
+    // Extend one step on each side, the origin may be anywhere in the sprite
+    let first = start - (start / step + 1) * step;
+    (0..)
+        .map(|i| first + i * step)
+        .take_while(|position| *position < len + step)
+        .collect()
+}
+
+fn draw_line(canvas: &mut WzImage, from: (i32, i32), to: (i32, i32), color: [u8; 4]) {
+    // Bresenham
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let step_x = if x < to.0 { 1 } else { -1 };
+    let step_y = if y < to.1 { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    loop {
+        blend_pixel(canvas, x, y, color);
+        if x == to.0 && y == to.1 {
+            break;
+        }
+
+        let error2 = error * 2;
+        if error2 >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if error2 <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+fn draw_rect(canvas: &mut WzImage, x: i32, y: i32, width: i32, height: i32, color: [u8; 4]) {
+    let (right, bottom) = (x + width - 1, y + height - 1);
+    draw_line(canvas, (x, y), (right, y), color);
+    draw_line(canvas, (right, y), (right, bottom), color);
+    draw_line(canvas, (right, bottom), (x, bottom), color);
+    draw_line(canvas, (x, bottom), (x, y), color);
+}
+
+pub fn save_map_png(
+    path: &str,
+    map: &Map,
+    map_wz: &ArcWzNode,
+    reader: Arc<WzReader>,
+    options: &MapRenderOptions,
+) -> Result<(), Error> {
+    save_png(path, &render_map(map, map_wz, reader, options)?)
+}
@@ -0,0 +1,17 @@
+pub mod nx_reader;
+pub mod nx_writer;
+
+pub use nx_reader::*;
+pub use nx_writer::*;
+
+pub const NX_MAGIC: &[u8; 4] = b"PKG4";
+pub const NX_HEADER_SIZE: usize = 52;
+pub const NX_NODE_SIZE: usize = 20;
+
+pub const NX_TYPE_NONE: u16 = 0;
+pub const NX_TYPE_INT64: u16 = 1;
+pub const NX_TYPE_DOUBLE: u16 = 2;
+pub const NX_TYPE_STRING: u16 = 3;
+pub const NX_TYPE_VECTOR: u16 = 4;
+pub const NX_TYPE_BITMAP: u16 = 5;
+pub const NX_TYPE_AUDIO: u16 = 6;
@@ -0,0 +1,429 @@
+use crate::{
+    parse_sound_format, write_canvas_payload, ArcWzNode, Vec2, WzCanvas, WzNode, WzReader, WzSound,
+    WzValue, WzValueCast, NX_HEADER_SIZE, NX_MAGIC, NX_NODE_SIZE, NX_TYPE_AUDIO, NX_TYPE_BITMAP,
+    NX_TYPE_DOUBLE, NX_TYPE_INT64, NX_TYPE_NONE, NX_TYPE_STRING, NX_TYPE_VECTOR,
+};
+use byteorder::{ByteOrder, LittleEndian};
+use flate2::{write::ZlibEncoder, Compression};
+use indexmap::IndexMap;
+use std::{
+    fs,
+    io::{Cursor, Error, ErrorKind, Result, Write},
+    sync::Arc,
+};
+
+struct NxHeader {
+    node_count: u32,
+    node_offset: u64,
+    string_count: u32,
+    string_offset: u64,
+    bitmap_count: u32,
+    bitmap_offset: u64,
+    audio_count: u32,
+    audio_offset: u64,
+}
+
+// A node as stored in the file, before its children are built
+struct NxRecord {
+    id: u32,
+    name: String,
+    first_child: u32,
+    children_count: u32,
+    node_type: u16,
+    data: [u8; 8],
+    inside_img: bool,
+    // Index of the first child in the list of records
+    first_record: usize,
+}
+
+impl NxRecord {
+    fn is_img(&self) -> bool {
+        !self.inside_img && self.name.ends_with(".img")
+    }
+}
+
+struct NxParser<'a> {
+    buffer: &'a [u8],
+    header: NxHeader,
+    // Canvas and sound payloads, repacked the way the .wz parser expects them
+    payloads: Vec<u8>,
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+// Offsets come from the file, so they can be anything
+fn add_offset(offset: u64, len: u64) -> Result<u64> {
+    offset
+        .checked_add(len)
+        .ok_or_else(|| invalid_data(format!("nx offset {} + {} overflows", offset, len)))
+}
+
+impl<'a> NxParser<'a> {
+    fn bytes(&self, offset: u64, len: usize) -> Result<&'a [u8]> {
+        let start = offset as usize;
+        self.buffer
+            .get(start..start.saturating_add(len))
+            .ok_or_else(|| invalid_data(format!("nx read out of bounds at {}", offset)))
+    }
+
+    // Offset of an entry in one of the string, bitmap or audio tables
+    fn table_entry(&self, table_offset: u64, count: u32, id: u32) -> Result<u64> {
+        if id >= count {
+            return Err(invalid_data(format!("nx table id {} out of range", id)));
+        }
+        Ok(LittleEndian::read_u64(
+            self.bytes(add_offset(table_offset, id as u64 * 8)?, 8)?,
+        ))
+    }
+
+    fn string(&self, id: u32) -> Result<String> {
+        let header = &self.header;
+        let offset = self.table_entry(header.string_offset, header.string_count, id)?;
+        let len = LittleEndian::read_u16(self.bytes(offset, 2)?) as usize;
+        let bytes = self.bytes(add_offset(offset, 2)?, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    fn bitmap(&mut self, id: u32, width: u32, height: u32) -> Result<WzCanvas> {
+        let header = &self.header;
+        let offset = self.table_entry(header.bitmap_offset, header.bitmap_count, id)?;
+        let len = LittleEndian::read_u32(self.bytes(offset, 4)?) as usize;
+        let compressed = self.bytes(add_offset(offset, 4)?, len)?;
+
+        // lz4 cannot expand more than 255 times, so a larger size is not worth allocating
+        let size = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .filter(|size| (*size as usize) <= compressed.len().saturating_mul(255))
+            .ok_or_else(|| invalid_data(format!("nx bitmap {} has an invalid size", id)))?;
+        let bgra = lz4_flex::block::decompress(compressed, size as usize)
+            .map_err(|e| invalid_data(e.to_string()))?;
+
+        // Store it as a zlib compressed bgra8888 canvas, so parse_canvas can read it
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&bgra)?;
+        let data = encoder.finish()?;
+
+        let canvas_offset = write_canvas_payload(&mut self.payloads, &data);
+
+        Ok(WzCanvas {
+            width,
+            height,
+            format1: 2,
+            format2: 0,
+            offset: canvas_offset,
+            origin: Vec2::default(),
+        })
+    }
+
+    // Audio blobs hold the sound header followed by the buffer, the same as NoLifeNx
+    fn audio(&mut self, id: u32, len: u32, name: &str) -> Result<WzSound> {
+        let header = &self.header;
+        let offset = self.table_entry(header.audio_offset, header.audio_count, id)?;
+        let blob = self.bytes(offset, len as usize)?;
+
+        let header_size = match blob.get(WzSound::SOUND_HEADER.len()) {
+            Some(wav_len) if blob.starts_with(&WzSound::SOUND_HEADER) => {
+                (WzSound::SOUND_HEADER.len() + 1 + *wav_len as usize).min(blob.len())
+            }
+            _ => 0,
+        };
+        let format = parse_sound_format(&blob[..header_size], None);
+
+        // NX does not keep the duration, so it comes from the byte rate
+        let buffer_size = blob.len() - header_size;
+        let duration = match &format {
+            Some(format) if format.avg_bytes_per_sec > 0 => {
+                (buffer_size as u64 * 1000 / format.avg_bytes_per_sec as u64) as u32
+            }
+            _ => 0,
+        };
+
+        let header_offset = self.payloads.len() as u64;
+        self.payloads.extend_from_slice(blob);
+
+        Ok(WzSound {
+            name: name.to_string(),
+            duration,
+            header_offset,
+            header_size,
+            buffer_offset: header_offset + header_size as u64,
+            buffer_size,
+            format,
+        })
+    }
+
+    fn record(&self, id: u32, inside_img: bool) -> Result<NxRecord> {
+        let node = self.bytes(
+            add_offset(self.header.node_offset, id as u64 * NX_NODE_SIZE as u64)?,
+            NX_NODE_SIZE,
+        )?;
+
+        let mut data = [0u8; 8];
+        data.copy_from_slice(&node[12..20]);
+
+        Ok(NxRecord {
+            id,
+            name: self.string(LittleEndian::read_u32(&node[0..4]))?,
+            first_child: LittleEndian::read_u32(&node[4..8]),
+            children_count: LittleEndian::read_u16(&node[8..10]) as u32,
+            node_type: LittleEndian::read_u16(&node[10..12]),
+            data,
+            inside_img,
+            first_record: 0,
+        })
+    }
+
+    // Read the nodes breadth first, then build them from the last one so children come first
+    fn root(&mut self) -> Result<WzNode> {
+        let node_count = self.header.node_count;
+        // The whole node table has to be in the file, which also bounds the allocations below
+        self.bytes(self.header.node_offset, node_count as usize * NX_NODE_SIZE)?;
+
+        // A node claimed by two parents would be shared, or loop forever
+        let mut claimed = vec![false; node_count as usize];
+        claimed[0] = true;
+
+        let mut records = vec![self.record(0, false)?];
+        let mut index = 0;
+        while index < records.len() {
+            let record = &records[index];
+            let id = record.id;
+            let inside_img = record.inside_img || record.is_img();
+            let first_child = record.first_child;
+            let last_child = first_child
+                .checked_add(record.children_count)
+                .filter(|last_child| *last_child <= node_count)
+                .ok_or_else(|| invalid_data(format!("nx node {} has invalid children", id)))?;
+
+            records[index].first_record = records.len();
+            for child_id in first_child..last_child {
+                if std::mem::replace(&mut claimed[child_id as usize], true) {
+                    return Err(invalid_data(format!(
+                        "nx node {} is a child of more than one node",
+                        child_id
+                    )));
+                }
+                records.push(self.record(child_id, inside_img)?);
+            }
+            index += 1;
+        }
+
+        let mut nodes: Vec<Option<WzNode>> = vec![];
+        nodes.resize_with(records.len(), || None);
+        for index in (0..records.len()).rev() {
+            let record = &records[index];
+            let children_range =
+                record.first_record..record.first_record + record.children_count as usize;
+            let children = nodes[children_range]
+                .iter_mut()
+                .filter_map(Option::take)
+                .map(|child| (child.name.clone(), Arc::new(child)))
+                .collect();
+            nodes[index] = Some(self.node(record, children)?);
+        }
+
+        nodes[0]
+            .take()
+            .ok_or_else(|| invalid_data("nx file has no root".to_string()))
+    }
+
+    fn node(&mut self, record: &NxRecord, children: IndexMap<String, ArcWzNode>) -> Result<WzNode> {
+        let name = &record.name;
+        let data = &record.data;
+        let value = match record.node_type {
+            NX_TYPE_NONE if record.is_img() => WzValue::Img,
+            NX_TYPE_NONE if !record.inside_img => WzValue::Directory,
+            NX_TYPE_NONE if children.is_empty() => WzValue::Null,
+            NX_TYPE_NONE => WzValue::Extended,
+            NX_TYPE_INT64 => {
+                let value = LittleEndian::read_i64(data);
+                match i32::try_from(value) {
+                    Ok(value) => WzValue::Int(value),
+                    Err(_) => WzValue::Long(value),
+                }
+            }
+            NX_TYPE_DOUBLE => WzValue::Double(LittleEndian::read_f64(data)),
+            NX_TYPE_STRING => WzValue::String(self.string(LittleEndian::read_u32(&data[0..4]))?),
+            NX_TYPE_VECTOR => WzValue::Vector(Vec2 {
+                x: LittleEndian::read_i32(&data[0..4]),
+                y: LittleEndian::read_i32(&data[4..8]),
+            }),
+            NX_TYPE_BITMAP => {
+                let bitmap_id = LittleEndian::read_u32(&data[0..4]);
+                let width = LittleEndian::read_u16(&data[4..6]) as u32;
+                let height = LittleEndian::read_u16(&data[6..8]) as u32;
+                let mut canvas = self.bitmap(bitmap_id, width, height)?;
+                if let Some(origin) = children.get("origin").and_then(|o| o.value.as_vector()) {
+                    canvas.origin = origin.clone();
+                }
+                WzValue::Canvas(canvas)
+            }
+            NX_TYPE_AUDIO => {
+                let audio_id = LittleEndian::read_u32(&data[0..4]);
+                let len = LittleEndian::read_u32(&data[4..8]);
+                WzValue::Sound(self.audio(audio_id, len, name)?)
+            }
+            node_type => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Unsupported nx node type: {} {}", name, node_type),
+            ))?,
+        };
+
+        Ok(WzNode::new_with_children(
+            name,
+            record.id as usize,
+            value,
+            children,
+        ))
+    }
+}
+
+fn parse_nx_header(buffer: &[u8]) -> Result<NxHeader> {
+    if buffer.len() < NX_HEADER_SIZE || &buffer[0..4] != NX_MAGIC {
+        return Err(Error::other("Invalid .nx file"));
+    }
+
+    Ok(NxHeader {
+        node_count: LittleEndian::read_u32(&buffer[4..8]),
+        node_offset: LittleEndian::read_u64(&buffer[8..16]),
+        string_count: LittleEndian::read_u32(&buffer[16..20]),
+        string_offset: LittleEndian::read_u64(&buffer[20..28]),
+        bitmap_count: LittleEndian::read_u32(&buffer[28..32]),
+        bitmap_offset: LittleEndian::read_u64(&buffer[32..40]),
+        audio_count: LittleEndian::read_u32(&buffer[40..44]),
+        audio_offset: LittleEndian::read_u64(&buffer[44..52]),
+    })
+}
+
+/// Read an NX (PKG4) file into a tree. Bitmaps and audio are repacked into the returned reader,
+/// so `parse_canvas` and `parse_sound_buffer` work the same as with .wz files.
+/// NX only has 64-bit integers and doubles, so integers come back as `Int` when they fit.
+pub fn parse_nx(buffer: &[u8]) -> Result<(ArcWzNode, Arc<WzReader>)> {
+    let header = parse_nx_header(buffer)?;
+    if header.node_count == 0 {
+        return Err(invalid_data("nx file has no nodes".to_string()));
+    }
+
+    let mut parser = NxParser {
+        buffer,
+        header,
+        payloads: vec![],
+    };
+    let root = parser.root()?;
+    let reader = WzReader::new(Cursor::new(parser.payloads), None);
+
+    Ok((Arc::new(root), reader.into()))
+}
+
+pub fn open_nx(path: &str) -> Result<(ArcWzNode, Arc<WzReader>)> {
+    let buffer = fs::read(path)?;
+    parse_nx(&buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parse_canvas, parse_sound_buffer, parse_sound_header, test_tree::test_tree, to_nx,
+    };
+
+    fn canvas_pixels(node: &ArcWzNode, reader: &Arc<WzReader>) -> (u32, u32, i32, i32, Vec<u8>) {
+        let WzValue::Canvas(canvas) = &node.value else {
+            panic!("{} is not a canvas", node.name);
+        };
+        let image = parse_canvas(canvas, reader.clone()).unwrap();
+        (
+            image.width,
+            image.height,
+            canvas.origin.x,
+            canvas.origin.y,
+            image.data,
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (root, reader) = test_tree();
+        let (nx_root, nx_reader) = parse_nx(&to_nx(&root, reader.clone()).unwrap()).unwrap();
+
+        assert!(matches!(nx_root.value, WzValue::Directory));
+        let img = &root.children["0100100.img"];
+        let nx_img = &nx_root.children["0100100.img"];
+        assert!(matches!(nx_img.value, WzValue::Img));
+
+        // NX only has 64-bit integers, doubles and strings
+        let values = |node: &ArcWzNode| {
+            [
+                "null", "short", "int", "long", "float", "double", "string", "vector", "uol",
+            ]
+            .map(|name| node.children[name].value.to_string())
+        };
+        assert_eq!(
+            values(nx_img),
+            [
+                WzValue::Null,
+                WzValue::Int(-7),
+                WzValue::Int(70000),
+                WzValue::Long(1 << 40),
+                WzValue::Double(0.5),
+                WzValue::Double(-2.25),
+                WzValue::String("a \"b\" <c> & 'd'\n".to_string()),
+                WzValue::Vector(Vec2 { x: -3, y: 4 }),
+                WzValue::String("stand/0".to_string()),
+            ]
+            .map(|value| value.to_string())
+        );
+        assert!(matches!(
+            nx_img.children["foothold"].value,
+            WzValue::Extended
+        ));
+        assert_eq!(nx_img.children["foothold"].children.len(), 2);
+
+        assert_eq!(
+            canvas_pixels(&img.children["stand"].children["0"], &reader),
+            canvas_pixels(&nx_img.children["stand"].children["0"], &nx_reader)
+        );
+
+        let (WzValue::Sound(sound), WzValue::Sound(nx_sound)) = (
+            &img.children["sound"].value,
+            &nx_img.children["sound"].value,
+        ) else {
+            panic!("expected sounds");
+        };
+        assert!(nx_sound.format.is_some());
+        assert_eq!(nx_sound.format, sound.format);
+        assert_eq!(nx_sound.duration, sound.duration);
+        assert_eq!(
+            parse_sound_header(nx_sound, nx_reader.clone()).unwrap(),
+            parse_sound_header(sound, reader.clone()).unwrap()
+        );
+        assert_eq!(
+            parse_sound_buffer(nx_sound, nx_reader).unwrap(),
+            parse_sound_buffer(sound, reader).unwrap()
+        );
+    }
+
+    fn set_children(nx: &mut [u8], id: usize, first_child: u32, count: u16) {
+        let offset = NX_HEADER_SIZE + id * NX_NODE_SIZE;
+        nx[offset + 4..offset + 8].copy_from_slice(&first_child.to_le_bytes());
+        nx[offset + 8..offset + 10].copy_from_slice(&count.to_le_bytes());
+    }
+
+    #[test]
+    fn test_invalid_children() {
+        let (root, reader) = test_tree();
+        let nx = to_nx(&root, reader).unwrap();
+
+        // The .img claiming itself, which the root already did
+        let mut claimed_twice = nx.clone();
+        set_children(&mut claimed_twice, 1, 1, 1);
+        assert!(parse_nx(&claimed_twice).is_err_and(|err| err.kind() == ErrorKind::InvalidData));
+
+        let mut out_of_range = nx.clone();
+        set_children(&mut out_of_range, 0, 1, u16::MAX);
+        assert!(parse_nx(&out_of_range).is_err_and(|err| err.kind() == ErrorKind::InvalidData));
+    }
+}
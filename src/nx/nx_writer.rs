@@ -0,0 +1,241 @@
+use crate::{
+    convert_image_bgra8888_to_rgba8888, parse_canvas, parse_sound_buffer, parse_sound_header,
+    ArcWzNode, WzReader, WzSound, WzValue, NX_HEADER_SIZE, NX_MAGIC, NX_TYPE_AUDIO, NX_TYPE_BITMAP,
+    NX_TYPE_DOUBLE, NX_TYPE_INT64, NX_TYPE_NONE, NX_TYPE_STRING, NX_TYPE_VECTOR,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufWriter, Error, ErrorKind, Result, Write},
+    sync::Arc,
+};
+
+#[derive(Default)]
+struct NxBuilder {
+    nodes: Vec<u8>,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    bitmaps: Vec<Vec<u8>>,
+    audio: Vec<Vec<u8>>,
+}
+
+impl NxBuilder {
+    fn add_string(&mut self, value: &str) -> u32 {
+        if let Some(id) = self.string_ids.get(value) {
+            return *id;
+        }
+
+        let id = self.strings.len() as u32;
+        self.strings.push(value.to_string());
+        self.string_ids.insert(value.to_string(), id);
+        id
+    }
+
+    // Type and 8 bytes of data for a node
+    fn node_data(&mut self, node: &ArcWzNode, reader: &Arc<WzReader>) -> (u16, [u8; 8]) {
+        let mut data = [0u8; 8];
+        let node_type = match &node.value {
+            WzValue::Short(val) => {
+                data.copy_from_slice(&(*val as i64).to_le_bytes());
+                NX_TYPE_INT64
+            }
+            WzValue::Int(val) => {
+                data.copy_from_slice(&(*val as i64).to_le_bytes());
+                NX_TYPE_INT64
+            }
+            WzValue::Long(val) => {
+                data.copy_from_slice(&val.to_le_bytes());
+                NX_TYPE_INT64
+            }
+            WzValue::Float(val) => {
+                data.copy_from_slice(&(*val as f64).to_le_bytes());
+                NX_TYPE_DOUBLE
+            }
+            WzValue::Double(val) => {
+                data.copy_from_slice(&val.to_le_bytes());
+                NX_TYPE_DOUBLE
+            }
+            // NX has no links, so UOLs are kept as their path
+            WzValue::String(val) | WzValue::Uol(val) => {
+                data[0..4].copy_from_slice(&self.add_string(val).to_le_bytes());
+                NX_TYPE_STRING
+            }
+            WzValue::Vector(val) => {
+                data[0..4].copy_from_slice(&val.x.to_le_bytes());
+                data[4..8].copy_from_slice(&val.y.to_le_bytes());
+                NX_TYPE_VECTOR
+            }
+            WzValue::Canvas(canvas) => match parse_canvas(canvas, reader.clone()) {
+                Ok(image) if image.width <= u16::MAX as u32 && image.height <= u16::MAX as u32 => {
+                    // Bitmaps are LZ4 compressed BGRA8888
+                    let bgra = convert_image_bgra8888_to_rgba8888(image.data);
+                    let id = self.bitmaps.len() as u32;
+                    self.bitmaps.push(lz4_flex::block::compress(&bgra));
+
+                    data[0..4].copy_from_slice(&id.to_le_bytes());
+                    data[4..6].copy_from_slice(&(image.width as u16).to_le_bytes());
+                    data[6..8].copy_from_slice(&(image.height as u16).to_le_bytes());
+                    NX_TYPE_BITMAP
+                }
+                Ok(image) => {
+                    log::warn!(
+                        "canvas {} is too large for nx ({}x{})",
+                        node.name,
+                        image.width,
+                        image.height
+                    );
+                    NX_TYPE_NONE
+                }
+                Err(err) => {
+                    log::warn!("failed to decode canvas {}: {}", node.name, err);
+                    NX_TYPE_NONE
+                }
+            },
+            WzValue::Sound(sound) => match read_audio(sound, reader) {
+                Ok(audio) => {
+                    let id = self.audio.len() as u32;
+                    data[0..4].copy_from_slice(&id.to_le_bytes());
+                    data[4..8].copy_from_slice(&(audio.len() as u32).to_le_bytes());
+                    self.audio.push(audio);
+                    NX_TYPE_AUDIO
+                }
+                Err(err) => {
+                    log::warn!("failed to read sound {}: {}", node.name, err);
+                    NX_TYPE_NONE
+                }
+            },
+            WzValue::Null
+            | WzValue::Directory
+            | WzValue::Img
+            | WzValue::Extended
+            | WzValue::Convex => NX_TYPE_NONE,
+        };
+
+        (node_type, data)
+    }
+
+    // Nodes are laid out breadth first, so the children of every node are contiguous
+    fn add_nodes(&mut self, root: &ArcWzNode, reader: &Arc<WzReader>) -> Result<u32> {
+        let mut queue = VecDeque::from([root.clone()]);
+        let mut node_count: usize = 1;
+
+        while let Some(node) = queue.pop_front() {
+            if node.children.len() > u16::MAX as usize {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{} has too many children for nx", node.name),
+                ));
+            }
+
+            // Children are sorted by name so readers can binary search them
+            let mut children: Vec<&ArcWzNode> = node.children.values().collect();
+            children.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+
+            let name = self.add_string(&node.name);
+            let first_child = if children.is_empty() { 0 } else { node_count };
+            let (node_type, data) = self.node_data(&node, reader);
+
+            self.nodes.extend_from_slice(&name.to_le_bytes());
+            self.nodes
+                .extend_from_slice(&(first_child as u32).to_le_bytes());
+            self.nodes
+                .extend_from_slice(&(children.len() as u16).to_le_bytes());
+            self.nodes.extend_from_slice(&node_type.to_le_bytes());
+            self.nodes.extend_from_slice(&data);
+
+            node_count += children.len();
+            queue.extend(children.into_iter().cloned());
+        }
+
+        Ok(node_count as u32)
+    }
+}
+
+// Like NoLifeNx, the header is kept in front of the buffer as it is in the file
+fn read_audio(sound: &WzSound, reader: &Arc<WzReader>) -> Result<Vec<u8>> {
+    let mut audio = parse_sound_header(sound, reader.clone())?;
+    audio.extend_from_slice(&parse_sound_buffer(sound, reader.clone())?);
+    Ok(audio)
+}
+
+fn pad_to(buffer: &mut Vec<u8>, alignment: usize) {
+    while !buffer.len().is_multiple_of(alignment) {
+        buffer.push(0);
+    }
+}
+
+// Write an offset table followed by each entry, returns the table offset
+fn write_table<F: Fn(&mut Vec<u8>, usize)>(
+    buffer: &mut Vec<u8>,
+    count: usize,
+    alignment: usize,
+    write_entry: F,
+) -> u64 {
+    if count == 0 {
+        return 0;
+    }
+
+    pad_to(buffer, 8);
+    let table_offset = buffer.len();
+    buffer.resize(table_offset + count * 8, 0);
+
+    for index in 0..count {
+        pad_to(buffer, alignment);
+        let entry_offset = buffer.len() as u64;
+        buffer[table_offset + index * 8..table_offset + index * 8 + 8]
+            .copy_from_slice(&entry_offset.to_le_bytes());
+        write_entry(buffer, index);
+    }
+
+    table_offset as u64
+}
+
+/// Convert a tree to the NX (PKG4) format. Canvases are decoded with `parse_canvas`,
+/// sounds are stored as their raw header and buffer.
+pub fn to_nx(node: &ArcWzNode, reader: Arc<WzReader>) -> Result<Vec<u8>> {
+    let mut builder = NxBuilder::default();
+    let node_count = builder.add_nodes(node, &reader)?;
+
+    let mut buffer = vec![0u8; NX_HEADER_SIZE];
+
+    let node_offset = buffer.len() as u64;
+    buffer.extend_from_slice(&builder.nodes);
+
+    let strings = &builder.strings;
+    let string_offset = write_table(&mut buffer, strings.len(), 2, |buffer, index| {
+        buffer.extend_from_slice(&(strings[index].len() as u16).to_le_bytes());
+        buffer.extend_from_slice(strings[index].as_bytes());
+    });
+
+    let bitmaps = &builder.bitmaps;
+    let bitmap_offset = write_table(&mut buffer, bitmaps.len(), 8, |buffer, index| {
+        buffer.extend_from_slice(&(bitmaps[index].len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&bitmaps[index]);
+    });
+
+    let audio = &builder.audio;
+    let audio_offset = write_table(&mut buffer, audio.len(), 8, |buffer, index| {
+        buffer.extend_from_slice(&audio[index]);
+    });
+
+    let mut header = Vec::with_capacity(NX_HEADER_SIZE);
+    header.extend_from_slice(NX_MAGIC);
+    header.extend_from_slice(&node_count.to_le_bytes());
+    header.extend_from_slice(&node_offset.to_le_bytes());
+    header.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+    header.extend_from_slice(&string_offset.to_le_bytes());
+    header.extend_from_slice(&(bitmaps.len() as u32).to_le_bytes());
+    header.extend_from_slice(&bitmap_offset.to_le_bytes());
+    header.extend_from_slice(&(audio.len() as u32).to_le_bytes());
+    header.extend_from_slice(&audio_offset.to_le_bytes());
+    buffer[..NX_HEADER_SIZE].copy_from_slice(&header);
+
+    Ok(buffer)
+}
+
+pub fn save_nx(path: &str, node: &ArcWzNode, reader: Arc<WzReader>) -> Result<()> {
+    let buffer = to_nx(node, reader)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&buffer)?;
+    writer.flush()
+}
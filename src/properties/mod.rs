@@ -1,7 +1,9 @@
 pub mod node;
 pub mod types;
 pub mod value;
+pub mod walk;
 
 pub use node::*;
 pub use types::*;
 pub use value::*;
+pub use walk::*;
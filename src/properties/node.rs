@@ -1,6 +1,6 @@
 use super::WzValue;
 use indexmap::IndexMap;
-use serde::{ser::SerializeMap, Serialize, Serializer};
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
 use std::{
     fmt,
     io::{Error, ErrorKind},
@@ -12,28 +12,43 @@ pub struct WzNode {
     pub offset: usize,
     pub value: WzValue,
     pub children: IndexMap<String, Arc<WzNode>>,
+    /// Only set for nodes listed in a directory
+    pub entry: Option<WzEntry>,
+}
+
+/// Size and checksum of a directory entry, as declared by its parent directory
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WzEntry {
+    pub size: i32,
+    pub checksum: i32,
 }
 
 pub type ArcWzNode = Arc<WzNode>;
 
 impl WzNode {
-    pub fn new(name: &String, offset: usize, value: impl Into<WzValue>) -> Self {
+    pub fn new(name: &str, offset: usize, value: impl Into<WzValue>) -> Self {
         Self::new_with_children(name, offset, value, IndexMap::new())
     }
 
     pub fn new_with_children(
-        name: &String,
+        name: &str,
         offset: usize,
         value: impl Into<WzValue>,
         children: IndexMap<String, ArcWzNode>,
     ) -> Self {
         Self {
-            name: name.clone(),
+            name: name.to_string(),
             offset,
             value: value.into(),
             children,
+            entry: None,
         }
     }
+
+    pub fn with_entry(mut self, entry: WzEntry) -> Self {
+        self.entry = Some(entry);
+        self
+    }
 }
 
 impl fmt::Display for WzNode {
@@ -0,0 +1,250 @@
+use crate::{
+    parse_canvas, resolve, resolve_uol_path, ArcWzNode, Vec2, WzImage, WzReader, WzValue,
+    WzValueCast,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Error, ErrorKind, Write},
+    sync::Arc,
+};
+
+/// Frames without a `delay` are shown for this long, in milliseconds
+pub const DEFAULT_FRAME_DELAY: u32 = 100;
+
+/// Alpha ramps (`a0` to `a1`) are split into steps of about this long, in milliseconds
+const ALPHA_RAMP_STEP: u32 = 30;
+
+#[derive(Default, Debug, Clone)]
+pub struct WzAnimationFrame {
+    /// RGBA pixels, already aligned on the animation canvas
+    pub image: WzImage,
+    /// Milliseconds
+    pub delay: u32,
+    /// Opacity at the start and the end of the frame
+    pub a0: u8,
+    pub a1: u8,
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct WzAnimation {
+    pub width: u32,
+    pub height: u32,
+    /// Position of the origin on the animation canvas, shared by every frame
+    pub origin: Vec2,
+    pub frames: Vec<WzAnimationFrame>,
+}
+
+/// Decode the numbered canvas children of the animation at `path` in an .img node, e.g.
+/// `stand` of a mob, and align them by `origin` onto a canvas large enough for every frame.
+/// UOL frames are resolved from the animation like the client does, so they can point anywhere
+/// in the .img. Frames that do not resolve are skipped.
+pub fn parse_animation(
+    img: &ArcWzNode,
+    path: &str,
+    reader: Arc<WzReader>,
+) -> Result<WzAnimation, Error> {
+    let node = if path.is_empty() {
+        img.clone()
+    } else {
+        resolve(img, path)?
+    };
+
+    let mut frame_nodes = node
+        .children
+        .iter()
+        .filter_map(|(name, child)| Some((name.parse::<u32>().ok()?, name, child)))
+        .collect::<Vec<_>>();
+    frame_nodes.sort_by_key(|(index, _, _)| *index);
+
+    let mut frames = vec![];
+    for (_, name, frame_node) in frame_nodes {
+        let frame_node = match &frame_node.value {
+            // Relative to the parent of the UOL, which is the animation
+            WzValue::Uol(uol) => match resolve_uol_path(path.to_string(), uol.clone())
+                .and_then(|frame_path| resolve(img, &frame_path))
+            {
+                Ok(frame_node) => frame_node,
+                Err(err) => {
+                    log::warn!("skipping frame {} of {}: {}", name, node.name, err);
+                    continue;
+                }
+            },
+            _ => frame_node.clone(),
+        };
+
+        let Some(canvas) = frame_node.value.as_canvas() else {
+            continue;
+        };
+
+        let get_int = |name: &str| {
+            frame_node
+                .children
+                .get(name)
+                .and_then(|child| match child.value {
+                    WzValue::Short(val) => Some(val as i32),
+                    WzValue::Int(val) => Some(val),
+                    WzValue::String(ref val) => val.parse().ok(),
+                    _ => None,
+                })
+        };
+
+        let a0 = get_int("a0").unwrap_or(255).clamp(0, 255) as u8;
+        frames.push(WzAnimationFrame {
+            image: parse_canvas(canvas, reader.clone())?,
+            delay: get_int("delay").map_or(DEFAULT_FRAME_DELAY, |delay| delay.max(0) as u32),
+            a0,
+            a1: get_int("a1").map_or(a0, |a1| a1.clamp(0, 255) as u8),
+        });
+    }
+
+    if frames.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No frames in animation {}", node.name),
+        ));
+    }
+
+    // Bounds of every frame relative to the shared origin
+    let left = frames
+        .iter()
+        .map(|frame| -frame.image.origin.x)
+        .min()
+        .unwrap_or(0);
+    let top = frames
+        .iter()
+        .map(|frame| -frame.image.origin.y)
+        .min()
+        .unwrap_or(0);
+    let right = frames
+        .iter()
+        .map(|frame| frame.image.width as i32 - frame.image.origin.x)
+        .max()
+        .unwrap_or(0);
+    let bottom = frames
+        .iter()
+        .map(|frame| frame.image.height as i32 - frame.image.origin.y)
+        .max()
+        .unwrap_or(0);
+
+    let width = (right - left) as u32;
+    let height = (bottom - top) as u32;
+    let origin = Vec2 { x: -left, y: -top };
+
+    for frame in &mut frames {
+        frame.image = align_image(&frame.image, width, height, &origin);
+    }
+
+    Ok(WzAnimation {
+        width,
+        height,
+        origin,
+        frames,
+    })
+}
+
+// Copy an image onto a larger transparent canvas, with its origin on the given origin
+fn align_image(image: &WzImage, width: u32, height: u32, origin: &Vec2) -> WzImage {
+    let mut data = vec![0; (width * height * 4) as usize];
+    let offset_x = (origin.x - image.origin.x) as usize;
+    let offset_y = (origin.y - image.origin.y) as usize;
+
+    let row_len = image.width as usize * 4;
+    for y in 0..image.height as usize {
+        let src = y * row_len;
+        let dst = ((offset_y + y) * width as usize + offset_x) * 4;
+        data[dst..dst + row_len].copy_from_slice(&image.data[src..src + row_len]);
+    }
+
+    WzImage {
+        width,
+        height,
+        origin: origin.clone(),
+        data,
+    }
+}
+
+// Expand the alpha ramps into extra frames, returning the pixels and delay of each
+fn render_frames(animation: &WzAnimation) -> Vec<(Vec<u8>, u32)> {
+    let mut rendered = vec![];
+    for frame in &animation.frames {
+        if frame.a0 == 255 && frame.a1 == 255 {
+            rendered.push((frame.image.data.clone(), frame.delay));
+            continue;
+        }
+
+        let steps = (frame.delay / ALPHA_RAMP_STEP).max(1);
+        for step in 0..steps {
+            let t = if steps > 1 {
+                step as f32 / (steps - 1) as f32
+            } else {
+                0.0
+            };
+            let alpha = frame.a0 as f32 + (frame.a1 as f32 - frame.a0 as f32) * t;
+
+            let mut data = frame.image.data.clone();
+            for pixel in data.chunks_exact_mut(4) {
+                pixel[3] = (pixel[3] as f32 * alpha / 255.0).round() as u8;
+            }
+
+            // Spread the delay so the steps add up to the frame delay
+            let delay = frame.delay * (step + 1) / steps - frame.delay * step / steps;
+            rendered.push((data, delay));
+        }
+    }
+    rendered
+}
+
+/// Encode an animation as an APNG that loops forever
+pub fn write_apng<W: Write>(animation: &WzAnimation, writer: W) -> Result<(), Error> {
+    let frames = render_frames(animation);
+
+    let mut encoder = png::Encoder::new(writer, animation.width, animation.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(Error::other)?;
+
+    let mut png_writer = encoder.write_header().map_err(Error::other)?;
+    for (data, delay) in &frames {
+        png_writer
+            .set_frame_delay((*delay).min(u16::MAX as u32) as u16, 1000)
+            .map_err(Error::other)?;
+        png_writer.write_image_data(data).map_err(Error::other)?;
+    }
+    png_writer.finish().map_err(Error::other)?;
+
+    Ok(())
+}
+
+pub fn save_apng(path: &str, animation: &WzAnimation) -> Result<(), Error> {
+    let file = File::create(path)?;
+    write_apng(animation, BufWriter::new(file))
+}
+
+/// Encode an animation as a GIF that loops forever. GIFs only have 256 colors and on/off
+/// transparency, so prefer APNG when the quality matters.
+pub fn write_gif<W: Write>(animation: &WzAnimation, writer: W) -> Result<(), Error> {
+    let width = u16::try_from(animation.width).map_err(Error::other)?;
+    let height = u16::try_from(animation.height).map_err(Error::other)?;
+
+    let mut encoder = gif::Encoder::new(writer, width, height, &[]).map_err(Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(Error::other)?;
+
+    for (mut data, delay) in render_frames(animation) {
+        let mut frame = gif::Frame::from_rgba_speed(width, height, &mut data, 10);
+        // GIF delays are in hundredths of a second
+        frame.delay = (delay / 10).min(u16::MAX as u32) as u16;
+        frame.dispose = gif::DisposalMethod::Background;
+        encoder.write_frame(&frame).map_err(Error::other)?;
+    }
+
+    Ok(())
+}
+
+pub fn save_gif(path: &str, animation: &WzAnimation) -> Result<(), Error> {
+    let file = File::create(path)?;
+    write_gif(animation, BufWriter::new(file))
+}
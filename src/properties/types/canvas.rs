@@ -2,15 +2,20 @@ use byteorder::{ByteOrder, LittleEndian};
 use inflate::inflate_bytes_zlib;
 use std::{
     fmt,
-    io::{Error, ErrorKind},
+    io::{Error, ErrorKind, Write},
     sync::Arc,
 };
 
 use crate::{
-    convert_image_bgra8888_to_rgba8888, decompress_image_bgr565_to_rgba8888,
-    decompress_image_bgra4444_to_rgba8888, decompress_image_dxt5_to_rgba8888, Vec2, WzImage,
-    WzReader,
+    compress_image_rgba8888_to_bgr565, compress_image_rgba8888_to_bgr565_block16,
+    compress_image_rgba8888_to_bgra4444, compress_image_rgba8888_to_dxt,
+    convert_image_bgra8888_to_rgba8888, convert_image_rgba8888_to_bgra8888,
+    decompress_image_bgr565_block16_to_rgba8888, decompress_image_bgr565_to_rgba8888,
+    decompress_image_bgra4444_to_rgba8888, decompress_image_dxt3_to_rgba8888,
+    decompress_image_dxt5_to_rgba8888, Vec2, WzImage, WzReader,
 };
+use flate2::{write::ZlibEncoder, Compression};
+use squish::{Algorithm, Format, Params};
 
 #[derive(Default, Debug, Clone)]
 
@@ -63,7 +68,7 @@ pub fn parse_canvas(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<WzImage,
             })
         }
         // bgr565
-        517 => {
+        513 => {
             let decompressed =
                 decompress_image_bgr565_to_rgba8888(&raw_image_bytes, canvas.width, canvas.height);
             Ok(WzImage {
@@ -73,8 +78,33 @@ pub fn parse_canvas(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<WzImage,
                 origin: canvas.origin.clone(),
             })
         }
+        // bgr565, one color per 16x16 block
+        517 => {
+            let decompressed = decompress_image_bgr565_block16_to_rgba8888(
+                &raw_image_bytes,
+                canvas.width,
+                canvas.height,
+            );
+            Ok(WzImage {
+                width: canvas.width,
+                height: canvas.height,
+                data: decompressed,
+                origin: canvas.origin.clone(),
+            })
+        }
+        // dxt3
+        1026 => {
+            let decompressed =
+                decompress_image_dxt3_to_rgba8888(&raw_image_bytes, canvas.width, canvas.height);
+            Ok(WzImage {
+                width: canvas.width,
+                height: canvas.height,
+                data: decompressed,
+                origin: canvas.origin.clone(),
+            })
+        }
         // dxt5
-        1026 | 2050 => {
+        2050 => {
             let decompressed =
                 decompress_image_dxt5_to_rgba8888(&raw_image_bytes, canvas.width, canvas.height);
             Ok(WzImage {
@@ -92,7 +122,7 @@ pub fn parse_canvas(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<WzImage,
 }
 
 fn get_raw_image(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<Vec<u8>, Error> {
-    let compressed_bytes = get_compressed_bytes(canvas, reader)?;
+    let compressed_bytes = parse_canvas_buffer(canvas, reader)?;
 
     let header_buf = &compressed_bytes[0..2];
     let header = LittleEndian::read_u16(header_buf);
@@ -114,7 +144,7 @@ fn get_raw_image(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<Vec<u8>, Er
         data = vec![];
         Err(Error::new(
             ErrorKind::Unsupported,
-            format!("Unsupported list wz image"),
+            "Unsupported list wz image",
         ))?
     }
 
@@ -122,20 +152,34 @@ fn get_raw_image(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<Vec<u8>, Er
     let uncompressed_size = match format {
         // inflate returns a vector with a size larger than the actual uncompressed image
         // so we need to calculate the uncompressed_size and splice the vector
-        1 => (canvas.width * canvas.height * 2) as usize,
+        1 | 513 => (canvas.width * canvas.height * 2) as usize,
         2 => (canvas.width * canvas.height * 4) as usize,
-        517 => (canvas.width * canvas.height / 128) as usize,
-        1026 | 2050 => (canvas.width * canvas.height) as usize,
-        _ => {
-            panic!("unhandled image format {}", format);
-        }
+        // One bgr565 color per 16x16 block
+        517 => (canvas.width.div_ceil(16) * canvas.height.div_ceil(16) * 2) as usize,
+        1026 => Format::Bc2.compressed_size(canvas.width as usize, canvas.height as usize),
+        2050 => Format::Bc3.compressed_size(canvas.width as usize, canvas.height as usize),
+        _ => Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Unsupported image format {}", format),
+        ))?,
     };
 
     let buf = inflate_bytes_zlib(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    if buf.len() < uncompressed_size {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "Image data is {} bytes, expected {}",
+                buf.len(),
+                uncompressed_size
+            ),
+        ));
+    }
     Ok(buf[..uncompressed_size].to_vec())
 }
 
-fn get_compressed_bytes(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<Vec<u8>, Error> {
+/// The canvas payload as stored in the file, usually zlib compressed
+pub fn parse_canvas_buffer(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<Vec<u8>, Error> {
     let current_position = reader.get_position()?;
     reader.seek(canvas.offset.into())?;
     let len = reader.read_u32()? - 1;
@@ -147,3 +191,180 @@ fn get_compressed_bytes(canvas: &WzCanvas, reader: Arc<WzReader>) -> Result<Vec<
 
     Ok(compressed_bytes)
 }
+
+/// Trade-off between speed and size or fidelity when encoding a canvas
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum WzCanvasQuality {
+    Fast,
+    #[default]
+    Normal,
+    Best,
+}
+
+/// Encode the RGBA pixels of an image into a canvas payload of the given format
+/// (1, 2, 513, 517, 1026 or 2050). Returns the canvas, with an offset of 0, and the zlib
+/// compressed payload as `parse_canvas_buffer` returns it.
+pub fn encode_canvas(
+    image: &WzImage,
+    format: u32,
+    quality: WzCanvasQuality,
+) -> Result<(WzCanvas, Vec<u8>), Error> {
+    if image.data.len() != (image.width * image.height * 4) as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Image data is {} bytes, expected {}x{} RGBA",
+                image.data.len(),
+                image.width,
+                image.height
+            ),
+        ));
+    }
+
+    let params = Params {
+        algorithm: match quality {
+            WzCanvasQuality::Fast => Algorithm::RangeFit,
+            WzCanvasQuality::Normal => Algorithm::ClusterFit,
+            WzCanvasQuality::Best => Algorithm::IterativeClusterFit,
+        },
+        weigh_colour_by_alpha: true,
+        ..Default::default()
+    };
+
+    let raw_image_bytes = match format {
+        1 => compress_image_rgba8888_to_bgra4444(&image.data),
+        2 => convert_image_rgba8888_to_bgra8888(&image.data),
+        513 => compress_image_rgba8888_to_bgr565(&image.data),
+        517 => compress_image_rgba8888_to_bgr565_block16(&image.data, image.width, image.height),
+        1026 => compress_image_rgba8888_to_dxt(
+            &image.data,
+            image.width,
+            image.height,
+            Format::Bc2,
+            params,
+        ),
+        2050 => compress_image_rgba8888_to_dxt(
+            &image.data,
+            image.width,
+            image.height,
+            Format::Bc3,
+            params,
+        ),
+        _ => Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Unsupported image format {}", format),
+        ))?,
+    };
+
+    let level = match quality {
+        WzCanvasQuality::Fast => Compression::fast(),
+        WzCanvasQuality::Normal => Compression::default(),
+        WzCanvasQuality::Best => Compression::best(),
+    };
+    let mut encoder = ZlibEncoder::new(vec![], level);
+    encoder.write_all(&raw_image_bytes)?;
+    let payload = encoder.finish()?;
+
+    let canvas = WzCanvas {
+        width: image.width,
+        height: image.height,
+        format1: format,
+        format2: 0,
+        offset: 0,
+        origin: image.origin.clone(),
+    };
+
+    Ok((canvas, payload))
+}
+
+/// Append a canvas payload the way it is stored in a file: length + 1, a zero byte, then the
+/// payload. Returns the offset to use for the canvas.
+pub fn write_canvas_payload(payloads: &mut Vec<u8>, data: &[u8]) -> u32 {
+    let offset = payloads.len() as u32;
+    payloads.extend_from_slice(&(data.len() as u32 + 1).to_le_bytes());
+    payloads.push(0);
+    payloads.extend_from_slice(data);
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_tree::test_image;
+    use std::io::Cursor;
+
+    const SIZES: [(u32, u32); 6] = [(1, 1), (5, 3), (16, 16), (17, 33), (33, 2), (31, 20)];
+
+    // Every block x block tile has one color that the format stores exactly. Low 565 values
+    // come back the same with or without replicating the high bits.
+    fn block_image(width: u32, height: u32, block: u32) -> WzImage {
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let tile = x / block + 3 * (y / block);
+                for (bit, value) in [(1, 24), (2, 60), (4, 16)] {
+                    data.push(if tile & bit == 0 { 0 } else { value });
+                }
+                data.push(255);
+            }
+        }
+
+        WzImage {
+            width,
+            height,
+            origin: Vec2 { x: 1, y: 2 },
+            data,
+        }
+    }
+
+    fn round_trip(image: &WzImage, format: u32) -> WzImage {
+        let (mut canvas, payload) = encode_canvas(image, format, WzCanvasQuality::Fast).unwrap();
+        let mut payloads = vec![];
+        canvas.offset = write_canvas_payload(&mut payloads, &payload);
+
+        let reader = WzReader::new(Cursor::new(payloads), None);
+        let decoded = parse_canvas(&canvas, reader.into()).unwrap();
+        assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+        assert_eq!((decoded.origin.x, decoded.origin.y), (1, 2));
+        assert_eq!(decoded.data.len(), image.data.len());
+        decoded
+    }
+
+    fn assert_close(image: &WzImage, decoded: &WzImage, tolerance: u8, format: u32) {
+        for (i, (a, b)) in image.data.iter().zip(&decoded.data).enumerate() {
+            assert!(
+                a.abs_diff(*b) <= tolerance,
+                "format {} {}x{}: byte {} is {}, expected {}",
+                format,
+                image.width,
+                image.height,
+                i,
+                b,
+                a
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (width, height) in SIZES {
+            let image = test_image(width, height);
+            assert_eq!(round_trip(&image, 2).data, image.data);
+            assert_close(&image, &round_trip(&image, 1), 8, 1);
+            assert_close(&image, &round_trip(&image, 513), 7, 513);
+
+            let image = block_image(width, height, 16);
+            assert_eq!(round_trip(&image, 517).data, image.data);
+
+            for format in [1026, 2050] {
+                let image = block_image(width, height, 4);
+                assert_eq!(
+                    round_trip(&image, format).data,
+                    image.data,
+                    "format {}",
+                    format
+                );
+            }
+        }
+    }
+}
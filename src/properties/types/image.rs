@@ -1,5 +1,9 @@
 use crate::Vec2;
-use std::fmt;
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Error, Write},
+};
 
 #[derive(Default, Debug, Clone)]
 pub struct WzImage {
@@ -21,3 +25,84 @@ impl fmt::Display for WzImage {
         )
     }
 }
+
+/// Encode the RGBA pixels of an image as PNG
+pub fn write_png<W: Write>(image: &WzImage, writer: W) -> Result<(), Error> {
+    let mut encoder = png::Encoder::new(writer, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut png_writer = encoder.write_header().map_err(Error::other)?;
+    png_writer
+        .write_image_data(&image.data)
+        .map_err(Error::other)?;
+
+    Ok(())
+}
+
+pub fn save_png(path: &str, image: &WzImage) -> Result<(), Error> {
+    let file = File::create(path)?;
+    write_png(image, BufWriter::new(file))
+}
+
+/// Alpha blend an image onto another with its origin at (x, y), mirrored horizontally when
+/// `flip` is set and with its opacity scaled by `alpha`
+pub fn draw_image(canvas: &mut WzImage, image: &WzImage, x: i32, y: i32, flip: bool, alpha: u8) {
+    let left = if flip {
+        x - (image.width as i32 - image.origin.x)
+    } else {
+        x - image.origin.x
+    };
+    let top = y - image.origin.y;
+
+    for image_y in 0..image.height as i32 {
+        let canvas_y = top + image_y;
+        if canvas_y < 0 || canvas_y >= canvas.height as i32 {
+            continue;
+        }
+
+        for image_x in 0..image.width as i32 {
+            let canvas_x = left + image_x;
+            if canvas_x < 0 || canvas_x >= canvas.width as i32 {
+                continue;
+            }
+
+            let source_x = if flip {
+                image.width as i32 - 1 - image_x
+            } else {
+                image_x
+            };
+            let source = ((image_y * image.width as i32 + source_x) * 4) as usize;
+            let mut pixel = [
+                image.data[source],
+                image.data[source + 1],
+                image.data[source + 2],
+                image.data[source + 3],
+            ];
+            pixel[3] = (pixel[3] as u32 * alpha as u32 / 255) as u8;
+            blend_pixel(canvas, canvas_x, canvas_y, pixel);
+        }
+    }
+}
+
+/// Source over, on a canvas that may itself be transparent. Pixels outside of it are ignored.
+pub fn blend_pixel(canvas: &mut WzImage, x: i32, y: i32, pixel: [u8; 4]) {
+    if x < 0 || y < 0 || x >= canvas.width as i32 || y >= canvas.height as i32 || pixel[3] == 0 {
+        return;
+    }
+
+    let index = ((y * canvas.width as i32 + x) * 4) as usize;
+    let destination = &mut canvas.data[index..index + 4];
+
+    let source_alpha = pixel[3] as f32 / 255.0;
+    let destination_alpha = destination[3] as f32 / 255.0;
+    let alpha = source_alpha + destination_alpha * (1.0 - source_alpha);
+
+    for channel in 0..3 {
+        let color = (pixel[channel] as f32 * source_alpha
+            + destination[channel] as f32 * destination_alpha * (1.0 - source_alpha))
+            / alpha;
+        destination[channel] = color.round() as u8;
+    }
+    destination[3] = (alpha * 255.0).round() as u8;
+}
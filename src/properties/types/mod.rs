@@ -1,9 +1,13 @@
 pub mod vec2;
+pub mod animation;
 pub mod canvas;
 pub mod image;
+pub mod pcm;
 pub mod sound;
 
 pub use vec2::*;
+pub use animation::*;
 pub use canvas::*;
 pub use image::*;
+pub use pcm::*;
 pub use sound::*;
@@ -0,0 +1,127 @@
+use crate::{
+    create_wav_header, parse_sound_buffer, parse_sound_format, parse_sound_header, WzReader,
+    WzSound,
+};
+use std::{
+    io::{Cursor, Error, ErrorKind},
+    sync::Arc,
+};
+use symphonia::core::{
+    audio::SampleBuffer, codecs::DecoderOptions, errors::Error as SymphoniaError,
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+/// Decoded samples of a sound
+#[derive(Default, Debug, Clone)]
+pub struct WzPcm {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Interleaved 16-bit samples
+    pub samples: Vec<i16>,
+}
+
+impl WzPcm {
+    /// Number of samples per channel
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        if self.sample_rate == 0 {
+            return 0;
+        }
+        self.frames() as u64 * 1000 / self.sample_rate as u64
+    }
+}
+
+/// Decode a sound to PCM. WAV buffers (PCM, float, ADPCM, A-law and mu-law) and MP3 buffers
+/// are supported, sounds without a wave format are assumed to be MP3.
+pub fn decode_sound(sound: &WzSound, reader: Arc<WzReader>) -> Result<WzPcm, Error> {
+    let buffer = parse_sound_buffer(sound, reader.clone())?;
+    let format = match &sound.format {
+        Some(format) => Some(format.clone()),
+        None => parse_sound_format(
+            &parse_sound_header(sound, reader.clone())?,
+            reader.wz_mutable_key.as_ref(),
+        ),
+    };
+
+    let mut hint = Hint::new();
+    let bytes = match format {
+        Some(format) if !format.is_mp3() => {
+            hint.with_extension("wav");
+            let mut bytes = create_wav_header(buffer.len(), &format);
+            bytes.extend_from_slice(&buffer);
+            bytes
+        }
+        _ => {
+            hint.with_extension("mp3");
+            buffer
+        }
+    };
+
+    decode_pcm(bytes, &hint).map_err(|err| match err {
+        SymphoniaError::IoError(err) => err,
+        SymphoniaError::Unsupported(message) => Error::new(ErrorKind::Unsupported, message),
+        err => Error::new(ErrorKind::InvalidData, err),
+    })
+}
+
+fn decode_pcm(bytes: Vec<u8>, hint: &Hint) -> Result<WzPcm, SymphoniaError> {
+    let source = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let probed = symphonia::default::get_probe().format(
+        hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format_reader = probed.format;
+
+    let track = format_reader
+        .default_track()
+        .ok_or(SymphoniaError::Unsupported("no audio track"))?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut pcm = WzPcm {
+        channels: track
+            .codec_params
+            .channels
+            .map_or(0, |channels| channels.count() as u16),
+        sample_rate: track.codec_params.sample_rate.unwrap_or_default(),
+        samples: vec![],
+    };
+
+    loop {
+        let packet = match format_reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt frame only loses that frame
+            Err(SymphoniaError::DecodeError(err)) => {
+                log::debug!("skipping sound frame: {}", err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let spec = *decoded.spec();
+        pcm.channels = spec.channels.count() as u16;
+        pcm.sample_rate = spec.rate;
+
+        let mut sample_buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+        sample_buffer.copy_interleaved_ref(decoded);
+        pcm.samples.extend_from_slice(sample_buffer.samples());
+    }
+
+    Ok(pcm)
+}
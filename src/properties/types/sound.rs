@@ -1,13 +1,16 @@
-use crate::WzReader;
+use crate::{safe_file_name, ArcWzNode, WzMutableKey, WzReader, WzValue};
+use byteorder::{ByteOrder, LittleEndian};
 use std::{
     fmt,
-    fs::File,
-    io::{BufWriter, Error, Write},
+    fs::{self, File},
+    io::{BufWriter, Error, ErrorKind, Write},
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
-const WAV_HEADER_SIZE: usize = 44;
-const PCM_SUBCHUNK_SIZE: usize = 16;
+// The media type GUIDs and a length byte precede the WAVEFORMATEX
+const WAVE_FORMAT_OFFSET: usize = WzSound::SOUND_HEADER.len() + 1;
+const WAVE_FORMAT_SIZE: usize = 18;
 
 #[derive(Default, Debug, Clone)]
 pub struct WzSound {
@@ -17,6 +20,8 @@ pub struct WzSound {
     pub header_size: usize,
     pub buffer_offset: u64,
     pub buffer_size: usize,
+    /// Parsed from the header, if it has one
+    pub format: Option<WzWaveFormat>,
 }
 
 impl WzSound {
@@ -54,93 +59,207 @@ pub fn parse_sound_buffer(sound: &WzSound, reader: Arc<WzReader>) -> Result<Vec<
     Ok(buffer_bytes)
 }
 
-pub fn save_sound(path: &str, sound: &WzSound, reader: Arc<WzReader>) -> std::io::Result<()> {
-    let sound_header = parse_sound_header(sound, reader.clone())?;
-    let sound_buffer = parse_sound_buffer(sound, reader.clone())?;
+/// The WAVEFORMATEX of a sound header
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct WzWaveFormat {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub samples_per_sec: u32,
+    pub avg_bytes_per_sec: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    /// Format specific data following the structure, e.g. MPEGLAYER3WAVEFORMAT fields for MP3
+    pub extra: Vec<u8>,
+}
 
-    let sound_type = match sound_header.len() {
-        0x46 => "wav",
-        _ => "mp3",
-    };
-
-    let file_path = format!("{}/{}.{}", path, sound.name, sound_type);
-    let file = File::create(file_path)?;
-    let mut writer = BufWriter::new(file);
-
-    match sound_type {
-        "wav" => {
-            // Ensure the header has enough data for the PCM subchunk
-            if sound_header.len() < 0x34 + PCM_SUBCHUNK_SIZE {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Invalid WAV header data",
-                ));
-            }
+impl WzWaveFormat {
+    pub const FORMAT_PCM: u16 = 0x0001;
+    pub const FORMAT_ADPCM: u16 = 0x0002;
+    pub const FORMAT_IEEE_FLOAT: u16 = 0x0003;
+    pub const FORMAT_ALAW: u16 = 0x0006;
+    pub const FORMAT_MULAW: u16 = 0x0007;
+    pub const FORMAT_MPEGLAYER3: u16 = 0x0055;
+
+    /// Parse a WAVEFORMATEX, the extra data size must match the length
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < WAVE_FORMAT_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Wave format is {} bytes, expected at least 18", bytes.len()),
+            ));
+        }
 
-            let sound_format: [u8; PCM_SUBCHUNK_SIZE] = sound_header
-                [0x34..0x34 + PCM_SUBCHUNK_SIZE]
-                .try_into()
-                .map_err(|_| {
-                    std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Failed to parse WAV format",
-                    )
-                })?;
+        let extra_size = LittleEndian::read_u16(&bytes[16..18]) as usize;
+        if WAVE_FORMAT_SIZE + extra_size != bytes.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Wave format extra size {} does not match its length {}",
+                    extra_size,
+                    bytes.len()
+                ),
+            ));
+        }
 
-            let wav_header = create_wav_header(sound.buffer_size, &sound_format);
+        Ok(Self {
+            format_tag: LittleEndian::read_u16(&bytes[0..2]),
+            channels: LittleEndian::read_u16(&bytes[2..4]),
+            samples_per_sec: LittleEndian::read_u32(&bytes[4..8]),
+            avg_bytes_per_sec: LittleEndian::read_u32(&bytes[8..12]),
+            block_align: LittleEndian::read_u16(&bytes[12..14]),
+            bits_per_sample: LittleEndian::read_u16(&bytes[14..16]),
+            extra: bytes[WAVE_FORMAT_SIZE..].to_vec(),
+        })
+    }
 
-            writer.write_all(&wav_header)?;
-            writer.write_all(&sound_buffer)?;
+    /// Serialize as a WAVEFORMATEX. PCM leaves out the extra size, as in a plain fmt chunk.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(WAVE_FORMAT_SIZE + self.extra.len());
+        bytes.extend_from_slice(&self.format_tag.to_le_bytes());
+        bytes.extend_from_slice(&self.channels.to_le_bytes());
+        bytes.extend_from_slice(&self.samples_per_sec.to_le_bytes());
+        bytes.extend_from_slice(&self.avg_bytes_per_sec.to_le_bytes());
+        bytes.extend_from_slice(&self.block_align.to_le_bytes());
+        bytes.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+        if !self.is_pcm() {
+            bytes.extend_from_slice(&(self.extra.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&self.extra);
         }
-        "mp3" => {
-            // Directly write the MP3 data
+        bytes
+    }
+
+    pub fn is_pcm(&self) -> bool {
+        self.format_tag == Self::FORMAT_PCM
+    }
+
+    pub fn is_mp3(&self) -> bool {
+        self.format_tag == Self::FORMAT_MPEGLAYER3
+    }
+
+    /// File extension of the container the buffer belongs in
+    pub fn extension(&self) -> &'static str {
+        if self.is_mp3() {
+            "mp3"
+        } else {
+            "wav"
+        }
+    }
+}
+
+impl fmt::Display for WzWaveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WzWaveFormat(format_tag: {:#06x}, channels: {}, samples_per_sec: {}, bits_per_sample: {})",
+            self.format_tag, self.channels, self.samples_per_sec, self.bits_per_sample
+        )
+    }
+}
+
+/// Parse the wave format of a Sound_DX8 header. Some clients encrypt it with the .wz key,
+/// which is only tried when the plain bytes do not make sense.
+pub fn parse_sound_format(header: &[u8], key: Option<&WzMutableKey>) -> Option<WzWaveFormat> {
+    let bytes = header.get(WAVE_FORMAT_OFFSET..)?;
+    if let Ok(format) = WzWaveFormat::parse(bytes) {
+        return Some(format);
+    }
+
+    let mut key = key?.clone();
+    let decrypted = bytes
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ key.at(i))
+        .collect::<Vec<_>>();
+    WzWaveFormat::parse(&decrypted).ok()
+}
+
+/// Write a sound to any sink, as a WAV file or the raw MP3 stream. Returns the extension
+/// matching what was written.
+pub fn write_sound<W: Write>(
+    sound: &WzSound,
+    reader: Arc<WzReader>,
+    mut writer: W,
+) -> Result<&'static str, Error> {
+    let sound_header = parse_sound_header(sound, reader.clone())?;
+    let sound_buffer = parse_sound_buffer(sound, reader.clone())?;
+
+    let format = sound
+        .format
+        .clone()
+        .or_else(|| parse_sound_format(&sound_header, reader.wz_mutable_key.as_ref()));
+
+    // Without a format the buffer is written as is, which is usually MP3
+    match format {
+        Some(format) if !format.is_mp3() => {
+            writer.write_all(&create_wav_header(sound_buffer.len(), &format))?;
             writer.write_all(&sound_buffer)?;
+            Ok("wav")
         }
         _ => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Unsupported sound format",
-            ));
+            writer.write_all(&sound_buffer)?;
+            Ok("mp3")
         }
     }
+}
+
+/// Save a sound in a directory as `<name>.wav` or `<name>.mp3`, returning the file path
+pub fn save_sound(path: &str, sound: &WzSound, reader: Arc<WzReader>) -> Result<PathBuf, Error> {
+    let mut buffer = vec![];
+    let extension = write_sound(sound, reader, &mut buffer)?;
+
+    let file_path = Path::new(path).join(format!("{}.{}", safe_file_name(&sound.name), extension));
+    let mut writer = BufWriter::new(File::create(&file_path)?);
+    writer.write_all(&buffer)?;
+    writer.flush()?;
 
-    Ok(())
+    Ok(file_path)
 }
 
-// Helper function to create a WAV header
-fn create_wav_header(
-    buffer_size: usize,
-    sound_format: &[u8; PCM_SUBCHUNK_SIZE],
-) -> [u8; WAV_HEADER_SIZE] {
-    //https://docs.fileformat.com/audio/wav/
-    const WAV_HEADER_TEMPLATE: [u8; WAV_HEADER_SIZE] = [
-        // RIFF chunk descriptor
-        0x52, 0x49, 0x46, 0x46, // "RIFF" in ASCII
-        0, 0, 0, 0, // Chunk size (file size - 8 bytes)
-        0x57, 0x41, 0x56, 0x45, // "WAVE" in ASCII
-        // fmt sub-chunk
-        0x66, 0x6d, 0x74, 0x20, // "fmt " in ASCII
-        0x10, 0, 0, 0, // Sub-chunk size (16 for PCM)
-        0, 0, // Audio format (1 = PCM, other values indicate compression)
-        0, 0, // Number of channels (1 for mono, 2 for stereo, etc.)
-        0, 0, 0, 0, // Sample rate (e.g., 44100 Hz)
-        0, 0, 0, 0, // Byte rate (sample rate * num channels * bits per sample / 8)
-        0, 0, // Block align (num channels * bits per sample / 8)
-        0, 0, // Bits per sample (e.g., 16 bits)
-        // data sub-chunk
-        0x64, 0x61, 0x74, 0x61, // "data" in ASCII
-        0, 0, 0, 0, // Sub-chunk 2 size (data size)
-    ];
+/// Save every sound below a node, in directories mirroring the node paths.
+/// Returns the number of sounds written.
+pub fn export_sounds(
+    node: &ArcWzNode,
+    reader: Arc<WzReader>,
+    output_dir: &Path,
+) -> Result<usize, Error> {
+    let mut count = 0;
+    for child in node.children.values() {
+        count += match &child.value {
+            WzValue::Sound(sound) => {
+                fs::create_dir_all(output_dir)?;
 
-    let riff_chunk_size = (buffer_size + 36).to_le_bytes();
-    let data_chunk_size = buffer_size.to_le_bytes();
+                let mut buffer = vec![];
+                let extension = write_sound(sound, reader.clone(), &mut buffer)?;
+                let file_name = format!("{}.{}", safe_file_name(&child.name), extension);
+                fs::write(output_dir.join(file_name), buffer)?;
+                1
+            }
+            // Node names can contain slashes, so each one is sanitized on its own
+            _ => export_sounds(
+                child,
+                reader.clone(),
+                &output_dir.join(safe_file_name(&child.name)),
+            )?,
+        };
+    }
+
+    Ok(count)
+}
 
-    let mut wav_header = WAV_HEADER_TEMPLATE;
+/// RIFF, fmt and data chunk headers for a WAV file, https://docs.fileformat.com/audio/wav/
+pub fn create_wav_header(buffer_size: usize, format: &WzWaveFormat) -> Vec<u8> {
+    let fmt_chunk = format.to_bytes();
 
-    // Fill chunk sizes
-    wav_header[4..8].copy_from_slice(&riff_chunk_size);
-    wav_header[40..44].copy_from_slice(&data_chunk_size);
-    wav_header[20..36].copy_from_slice(sound_format);
+    let mut wav_header = Vec::with_capacity(28 + fmt_chunk.len());
+    wav_header.extend_from_slice(b"RIFF");
+    // Chunk size (file size - 8 bytes)
+    wav_header.extend_from_slice(&((20 + fmt_chunk.len() + buffer_size) as u32).to_le_bytes());
+    wav_header.extend_from_slice(b"WAVE");
+    wav_header.extend_from_slice(b"fmt ");
+    wav_header.extend_from_slice(&(fmt_chunk.len() as u32).to_le_bytes());
+    wav_header.extend_from_slice(&fmt_chunk);
+    wav_header.extend_from_slice(b"data");
+    wav_header.extend_from_slice(&(buffer_size as u32).to_le_bytes());
 
     wav_header
 }
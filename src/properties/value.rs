@@ -22,6 +22,66 @@ pub enum WzValue {
     Uol(String),
 }
 
+impl WzValue {
+    /// Short lowercase name of the variant, e.g. `int` or `canvas`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            WzValue::Null => "null",
+            WzValue::Directory => "directory",
+            WzValue::Img => "img",
+            WzValue::Extended => "extended",
+            WzValue::Convex => "convex",
+            WzValue::Short(_) => "short",
+            WzValue::Int(_) => "int",
+            WzValue::Long(_) => "long",
+            WzValue::Float(_) => "float",
+            WzValue::Double(_) => "double",
+            WzValue::String(_) => "string",
+            WzValue::Vector(_) => "vector",
+            WzValue::Canvas(_) => "canvas",
+            WzValue::Sound(_) => "sound",
+            WzValue::Uol(_) => "uol",
+        }
+    }
+
+    /// Integer value of any numeric variant, or of a string holding a number
+    pub fn to_i32(&self) -> Option<i32> {
+        match self {
+            WzValue::Short(val) => Some(*val as i32),
+            WzValue::Int(val) => Some(*val),
+            WzValue::Long(val) => i32::try_from(*val).ok(),
+            WzValue::Float(val) => Some(*val as i32),
+            WzValue::Double(val) => Some(*val as i32),
+            WzValue::String(val) => val.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Floating point value of any numeric variant, or of a string holding a number
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            WzValue::Short(val) => Some(*val as f64),
+            WzValue::Int(val) => Some(*val as f64),
+            WzValue::Long(val) => Some(*val as f64),
+            WzValue::Float(val) => Some(*val as f64),
+            WzValue::Double(val) => Some(*val),
+            WzValue::String(val) => val.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Text of a string or uol, or the decimal form of an integer
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            WzValue::String(val) | WzValue::Uol(val) => Some(val.clone()),
+            WzValue::Short(val) => Some(val.to_string()),
+            WzValue::Int(val) => Some(val.to_string()),
+            WzValue::Long(val) => Some(val.to_string()),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for WzValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -0,0 +1,159 @@
+use crate::{join_path, ArcWzNode, Vec2, WzCanvas, WzSound, WzValue};
+use std::collections::VecDeque;
+
+/// Depth-first iterator over every node below a node, yielding paths relative to it.
+/// Children of the last yielded node are only queued on the next call, so `skip_children`
+/// can prune them.
+pub struct WzDepthFirstIter<'a> {
+    stack: Vec<(String, &'a ArcWzNode)>,
+    last: Option<(String, &'a ArcWzNode)>,
+}
+
+impl WzDepthFirstIter<'_> {
+    /// Do not descend into the node that was just returned
+    pub fn skip_children(&mut self) {
+        self.last = None;
+    }
+}
+
+impl<'a> Iterator for WzDepthFirstIter<'a> {
+    type Item = (String, &'a ArcWzNode);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((path, node)) = self.last.take() {
+            // Reversed, so the first child is popped first
+            for (name, child) in node.children.iter().rev() {
+                self.stack.push((join_path(&path, name), child));
+            }
+        }
+
+        let (path, node) = self.stack.pop()?;
+        self.last = Some((path.clone(), node));
+        Some((path, node))
+    }
+}
+
+/// Breadth-first iterator over every node below a node, yielding paths relative to it
+pub struct WzBreadthFirstIter<'a> {
+    queue: VecDeque<(String, &'a ArcWzNode)>,
+    last: Option<(String, &'a ArcWzNode)>,
+}
+
+impl WzBreadthFirstIter<'_> {
+    /// Do not descend into the node that was just returned
+    pub fn skip_children(&mut self) {
+        self.last = None;
+    }
+}
+
+impl<'a> Iterator for WzBreadthFirstIter<'a> {
+    type Item = (String, &'a ArcWzNode);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((path, node)) = self.last.take() {
+            for (name, child) in &node.children {
+                self.queue.push_back((join_path(&path, name), child));
+            }
+        }
+
+        let (path, node) = self.queue.pop_front()?;
+        self.last = Some((path.clone(), node));
+        Some((path, node))
+    }
+}
+
+pub fn walk_depth_first(node: &ArcWzNode) -> WzDepthFirstIter<'_> {
+    WzDepthFirstIter {
+        stack: vec![],
+        last: Some((String::new(), node)),
+    }
+}
+
+pub fn walk_breadth_first(node: &ArcWzNode) -> WzBreadthFirstIter<'_> {
+    WzBreadthFirstIter {
+        queue: VecDeque::new(),
+        last: Some((String::new(), node)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WzWalkControl {
+    Continue,
+    SkipChildren,
+    Stop,
+}
+
+/// Callbacks for `visit`, every method does nothing by default
+#[allow(unused_variables)]
+pub trait WzVisitor {
+    /// Called for every node before its value callback
+    fn enter(&mut self, path: &str, node: &ArcWzNode) -> WzWalkControl {
+        WzWalkControl::Continue
+    }
+
+    /// Called once all the children of a node were visited
+    fn leave(&mut self, path: &str, node: &ArcWzNode) {}
+
+    fn on_null(&mut self, path: &str, node: &ArcWzNode) {}
+    fn on_directory(&mut self, path: &str, node: &ArcWzNode) {}
+    fn on_img(&mut self, path: &str, node: &ArcWzNode) {}
+    fn on_extended(&mut self, path: &str, node: &ArcWzNode) {}
+    fn on_convex(&mut self, path: &str, node: &ArcWzNode) {}
+    fn on_short(&mut self, path: &str, node: &ArcWzNode, value: i16) {}
+    fn on_int(&mut self, path: &str, node: &ArcWzNode, value: i32) {}
+    fn on_long(&mut self, path: &str, node: &ArcWzNode, value: i64) {}
+    fn on_float(&mut self, path: &str, node: &ArcWzNode, value: f32) {}
+    fn on_double(&mut self, path: &str, node: &ArcWzNode, value: f64) {}
+    fn on_string(&mut self, path: &str, node: &ArcWzNode, value: &str) {}
+    fn on_vector(&mut self, path: &str, node: &ArcWzNode, value: &Vec2) {}
+    fn on_canvas(&mut self, path: &str, node: &ArcWzNode, value: &WzCanvas) {}
+    fn on_sound(&mut self, path: &str, node: &ArcWzNode, value: &WzSound) {}
+    fn on_uol(&mut self, path: &str, node: &ArcWzNode, value: &str) {}
+}
+
+/// Walk every node below this one depth-first, calling the visitor for each.
+/// Returns false if the visitor stopped the walk.
+pub fn visit<V: WzVisitor + ?Sized>(node: &ArcWzNode, visitor: &mut V) -> bool {
+    visit_children(node, "", visitor)
+}
+
+fn visit_children<V: WzVisitor + ?Sized>(node: &ArcWzNode, path: &str, visitor: &mut V) -> bool {
+    for (name, child) in &node.children {
+        let child_path = join_path(path, name);
+        match visitor.enter(&child_path, child) {
+            WzWalkControl::Stop => return false,
+            WzWalkControl::SkipChildren => {
+                visit_value(&child_path, child, visitor);
+            }
+            WzWalkControl::Continue => {
+                visit_value(&child_path, child, visitor);
+                if !visit_children(child, &child_path, visitor) {
+                    return false;
+                }
+            }
+        }
+        visitor.leave(&child_path, child);
+    }
+
+    true
+}
+
+fn visit_value<V: WzVisitor + ?Sized>(path: &str, node: &ArcWzNode, visitor: &mut V) {
+    match &node.value {
+        WzValue::Null => visitor.on_null(path, node),
+        WzValue::Directory => visitor.on_directory(path, node),
+        WzValue::Img => visitor.on_img(path, node),
+        WzValue::Extended => visitor.on_extended(path, node),
+        WzValue::Convex => visitor.on_convex(path, node),
+        WzValue::Short(val) => visitor.on_short(path, node, *val),
+        WzValue::Int(val) => visitor.on_int(path, node, *val),
+        WzValue::Long(val) => visitor.on_long(path, node, *val),
+        WzValue::Float(val) => visitor.on_float(path, node, *val),
+        WzValue::Double(val) => visitor.on_double(path, node, *val),
+        WzValue::String(val) => visitor.on_string(path, node, val),
+        WzValue::Vector(val) => visitor.on_vector(path, node, val),
+        WzValue::Canvas(val) => visitor.on_canvas(path, node, val),
+        WzValue::Sound(val) => visitor.on_sound(path, node, val),
+        WzValue::Uol(val) => visitor.on_uol(path, node, val),
+    }
+}
@@ -0,0 +1,3 @@
+pub mod string_table;
+
+pub use string_table::*;
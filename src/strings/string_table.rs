@@ -0,0 +1,199 @@
+use crate::{ArcWzNode, WzNode};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+};
+
+/// Name and description of an item, mob, npc or skill
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StringEntry {
+    pub name: String,
+    pub desc: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapString {
+    pub street_name: String,
+    pub map_name: String,
+    pub desc: Option<String>,
+}
+
+/// Images of String.wz holding item names
+pub const ITEM_STRING_IMGS: [&str; 6] = [
+    "Eqp.img",
+    "Consume.img",
+    "Ins.img",
+    "Etc.img",
+    "Cash.img",
+    "Pet.img",
+];
+
+/// Names from String.wz by id
+#[derive(Debug, Clone, Default)]
+pub struct StringTable {
+    pub items: HashMap<i32, StringEntry>,
+    pub mobs: HashMap<i32, StringEntry>,
+    pub npcs: HashMap<i32, StringEntry>,
+    pub skills: HashMap<i32, StringEntry>,
+    pub maps: HashMap<i32, MapString>,
+}
+
+impl StringTable {
+    pub fn item_name(&self, id: i32) -> Option<&str> {
+        self.items.get(&id).map(|entry| entry.name.as_str())
+    }
+
+    pub fn item_desc(&self, id: i32) -> Option<&str> {
+        self.items.get(&id).and_then(|entry| entry.desc.as_deref())
+    }
+
+    pub fn mob_name(&self, id: i32) -> Option<&str> {
+        self.mobs.get(&id).map(|entry| entry.name.as_str())
+    }
+
+    pub fn npc_name(&self, id: i32) -> Option<&str> {
+        self.npcs.get(&id).map(|entry| entry.name.as_str())
+    }
+
+    pub fn skill_name(&self, id: i32) -> Option<&str> {
+        self.skills.get(&id).map(|entry| entry.name.as_str())
+    }
+
+    pub fn skill_desc(&self, id: i32) -> Option<&str> {
+        self.skills.get(&id).and_then(|entry| entry.desc.as_deref())
+    }
+
+    /// The street and map name, e.g. `Victoria Road - Henesys`
+    pub fn map_name(&self, id: i32) -> Option<String> {
+        let map = self.maps.get(&id)?;
+        if map.street_name.is_empty() {
+            Some(map.map_name.clone())
+        } else {
+            Some(format!("{} - {}", map.street_name, map.map_name))
+        }
+    }
+
+    /// Ids of the items with this name, ignoring case
+    pub fn item_ids(&self, name: &str) -> Vec<i32> {
+        find_ids(&self.items, name, |entry| &entry.name)
+    }
+
+    pub fn mob_ids(&self, name: &str) -> Vec<i32> {
+        find_ids(&self.mobs, name, |entry| &entry.name)
+    }
+
+    pub fn npc_ids(&self, name: &str) -> Vec<i32> {
+        find_ids(&self.npcs, name, |entry| &entry.name)
+    }
+
+    pub fn skill_ids(&self, name: &str) -> Vec<i32> {
+        find_ids(&self.skills, name, |entry| &entry.name)
+    }
+
+    /// Ids of the maps with this map name, ignoring case and the street name
+    pub fn map_ids(&self, name: &str) -> Vec<i32> {
+        find_ids(&self.maps, name, |map| &map.map_name)
+    }
+}
+
+// Sorted so that the result does not depend on the hash order
+fn find_ids<T>(
+    entries: &HashMap<i32, T>,
+    name: &str,
+    get_name: impl Fn(&T) -> &String,
+) -> Vec<i32> {
+    let name = name.to_lowercase();
+    let mut ids = entries
+        .iter()
+        .filter(|(_, entry)| get_name(entry).to_lowercase() == name)
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids
+}
+
+fn get_text(node: &WzNode, name: &str) -> Option<String> {
+    node.children
+        .get(name)
+        .and_then(|child| child.value.to_text())
+}
+
+// Entries are named by their id, under any number of groups such as the category in
+// Eqp.img or the region in Map.img
+fn collect_entries<T>(
+    node: &ArcWzNode,
+    entries: &mut HashMap<i32, T>,
+    parse_entry: &impl Fn(&WzNode) -> Option<T>,
+) {
+    for (name, child) in &node.children {
+        let entry = name
+            .parse::<i32>()
+            .ok()
+            .and_then(|id| Some((id, parse_entry(child)?)));
+        if let Some((id, entry)) = entry {
+            entries.insert(id, entry);
+        } else {
+            collect_entries(child, entries, parse_entry);
+        }
+    }
+}
+
+fn parse_entry(node: &WzNode) -> Option<StringEntry> {
+    Some(StringEntry {
+        name: get_text(node, "name")?,
+        desc: get_text(node, "desc"),
+    })
+}
+
+fn parse_map_string(node: &WzNode) -> Option<MapString> {
+    let street_name = get_text(node, "streetName");
+    let map_name = get_text(node, "mapName");
+    if street_name.is_none() && map_name.is_none() {
+        return None;
+    }
+
+    Some(MapString {
+        street_name: street_name.unwrap_or_default(),
+        map_name: map_name.unwrap_or_default(),
+        desc: get_text(node, "mapDesc"),
+    })
+}
+
+/// Load every name from a parsed String.wz node, missing images are left empty
+pub fn parse_string_table(string_wz: &ArcWzNode) -> Result<StringTable, Error> {
+    let mut table = StringTable::default();
+
+    let mut found = false;
+    let mut load = |img: &str| {
+        let node = string_wz.children.get(img);
+        found |= node.is_some();
+        node
+    };
+
+    for img in ITEM_STRING_IMGS {
+        if let Some(node) = load(img) {
+            collect_entries(node, &mut table.items, &parse_entry);
+        }
+    }
+    if let Some(node) = load("Mob.img") {
+        collect_entries(node, &mut table.mobs, &parse_entry);
+    }
+    if let Some(node) = load("Npc.img") {
+        collect_entries(node, &mut table.npcs, &parse_entry);
+    }
+    if let Some(node) = load("Skill.img") {
+        collect_entries(node, &mut table.skills, &parse_entry);
+    }
+    if let Some(node) = load("Map.img") {
+        collect_entries(node, &mut table.maps, &parse_map_string);
+    }
+
+    if !found {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} has no String.wz images", string_wz.name),
+        ));
+    }
+
+    Ok(table)
+}
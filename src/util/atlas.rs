@@ -0,0 +1,255 @@
+use crate::{
+    parse_canvas, save_png, walk_depth_first, ArcWzNode, Vec2, WzImage, WzReader, WzValue,
+};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+    sync::Arc,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct WzAtlasOptions {
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Transparent pixels between sprites, avoids bleeding when sampling
+    pub padding: u32,
+}
+
+impl Default for WzAtlasOptions {
+    fn default() -> Self {
+        Self {
+            max_width: 2048,
+            max_height: 2048,
+            padding: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WzAtlasSprite {
+    pub path: String,
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub origin: Vec2,
+    /// Other values of the canvas node, e.g. `delay` and `z`
+    pub metadata: IndexMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct WzAtlas {
+    /// RGBA pages, trimmed to the space used
+    pub pages: Vec<WzImage>,
+    pub sprites: Vec<WzAtlasSprite>,
+}
+
+#[derive(Serialize)]
+struct WzAtlasManifest<'a> {
+    pages: Vec<String>,
+    sprites: &'a [WzAtlasSprite],
+}
+
+// Rows of sprites, the tallest sprites are placed first
+#[derive(Default)]
+struct Page {
+    shelves: Vec<Shelf>,
+    width: u32,
+    height: u32,
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32,
+}
+
+/// Every canvas below a node, with its path relative to the node
+pub fn collect_canvases(node: &ArcWzNode) -> Vec<(String, ArcWzNode)> {
+    walk_depth_first(node)
+        .filter(|(_, child)| matches!(child.value, WzValue::Canvas(_)))
+        .map(|(path, child)| (path, child.clone()))
+        .collect()
+}
+
+/// Decode the canvases and pack them into as few pages as possible
+pub fn build_atlas(
+    canvases: &[(String, ArcWzNode)],
+    reader: Arc<WzReader>,
+    options: WzAtlasOptions,
+) -> Result<WzAtlas> {
+    let mut images = vec![];
+    for (path, node) in canvases {
+        let WzValue::Canvas(canvas) = &node.value else {
+            continue;
+        };
+
+        let image = parse_canvas(canvas, reader.clone())?;
+        if image.width + options.padding * 2 > options.max_width
+            || image.height + options.padding * 2 > options.max_height
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} is {}x{}, larger than a {}x{} page",
+                    path, image.width, image.height, options.max_width, options.max_height
+                ),
+            ));
+        }
+
+        images.push((path, node, image));
+    }
+
+    // Tallest first, so each shelf wastes as little height as possible
+    let mut order = (0..images.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&index| std::cmp::Reverse(images[index].2.height));
+
+    let mut pages: Vec<Page> = vec![];
+    let mut placements = vec![(0, 0, 0); images.len()];
+    for index in order {
+        let image = &images[index].2;
+        let width = image.width + options.padding * 2;
+        let height = image.height + options.padding * 2;
+
+        let placement = pages.iter_mut().enumerate().find_map(|(page_index, page)| {
+            place(page, width, height, &options).map(|(x, y)| (page_index, x, y))
+        });
+
+        placements[index] = match placement {
+            Some(placement) => placement,
+            None => {
+                let mut page = Page::default();
+                let (x, y) = place(&mut page, width, height, &options)
+                    .ok_or_else(|| Error::other("Sprite does not fit in an empty page"))?;
+                pages.push(page);
+                (pages.len() - 1, x, y)
+            }
+        };
+    }
+
+    let mut atlas = WzAtlas {
+        pages: pages
+            .iter()
+            .map(|page| WzImage {
+                width: page.width,
+                height: page.height,
+                data: vec![0; (page.width * page.height * 4) as usize],
+                ..Default::default()
+            })
+            .collect(),
+        sprites: vec![],
+    };
+
+    for ((path, node, image), (page_index, x, y)) in images.into_iter().zip(placements) {
+        let x = x + options.padding;
+        let y = y + options.padding;
+        blit(&mut atlas.pages[page_index], &image, x, y);
+
+        let metadata = node
+            .children
+            .iter()
+            .filter(|(name, _)| name.as_str() != "origin")
+            .filter_map(|(name, child)| {
+                let value = match &child.value {
+                    WzValue::Vector(val) => serde_json::to_value(val).ok()?,
+                    WzValue::Short(_)
+                    | WzValue::Int(_)
+                    | WzValue::Long(_)
+                    | WzValue::Float(_)
+                    | WzValue::Double(_)
+                    | WzValue::String(_)
+                    | WzValue::Uol(_) => serde_json::to_value(&child.value).ok()?,
+                    _ => return None,
+                };
+                Some((name.clone(), value))
+            })
+            .collect();
+
+        atlas.sprites.push(WzAtlasSprite {
+            path: path.clone(),
+            page: page_index,
+            x,
+            y,
+            width: image.width,
+            height: image.height,
+            origin: image.origin,
+            metadata,
+        });
+    }
+
+    Ok(atlas)
+}
+
+// Find room on an existing shelf, or open a new one below the others
+fn place(page: &mut Page, width: u32, height: u32, options: &WzAtlasOptions) -> Option<(u32, u32)> {
+    let position = match page
+        .shelves
+        .iter_mut()
+        .find(|shelf| shelf.height >= height && shelf.x + width <= options.max_width)
+    {
+        Some(shelf) => {
+            let position = (shelf.x, shelf.y);
+            shelf.x += width;
+            position
+        }
+        None => {
+            let y = page
+                .shelves
+                .last()
+                .map_or(0, |shelf| shelf.y + shelf.height);
+            if y + height > options.max_height {
+                return None;
+            }
+
+            page.shelves.push(Shelf {
+                y,
+                height,
+                x: width,
+            });
+            (0, y)
+        }
+    };
+
+    page.width = page.width.max(position.0 + width);
+    page.height = page.height.max(position.1 + height);
+    Some(position)
+}
+
+fn blit(page: &mut WzImage, image: &WzImage, x: u32, y: u32) {
+    let row_len = image.width as usize * 4;
+    for row in 0..image.height as usize {
+        let src = row * row_len;
+        let dst = ((y as usize + row) * page.width as usize + x as usize) * 4;
+        page.data[dst..dst + row_len].copy_from_slice(&image.data[src..src + row_len]);
+    }
+}
+
+impl WzAtlas {
+    /// JSON manifest listing the page files and every sprite
+    pub fn to_manifest_json(&self, page_names: &[String]) -> Result<String> {
+        let manifest = WzAtlasManifest {
+            pages: page_names.to_vec(),
+            sprites: &self.sprites,
+        };
+        serde_json::to_string_pretty(&manifest).map_err(Error::other)
+    }
+
+    /// Write the pages as `<name>_<page>.png` and the manifest as `<name>.json`
+    pub fn save(&self, output_dir: &Path, name: &str) -> Result<()> {
+        fs::create_dir_all(output_dir)?;
+
+        let mut page_names = vec![];
+        for (index, page) in self.pages.iter().enumerate() {
+            let page_name = format!("{}_{}.png", name, index);
+            save_png(&output_dir.join(&page_name).to_string_lossy(), page)?;
+            page_names.push(page_name);
+        }
+
+        let manifest = self.to_manifest_json(&page_names)?;
+        fs::write(output_dir.join(format!("{}.json", name)), manifest)
+    }
+}
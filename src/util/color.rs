@@ -1,4 +1,4 @@
-use squish::Format;
+use squish::{Format, Params};
 
 pub fn decompress_image_bgra4444_to_rgba8888(data: &[u8], width: u32, height: u32) -> Vec<u8> {
     fn extract_lower_bits(bits: u8) -> u8 {
@@ -12,8 +12,7 @@ pub fn decompress_image_bgra4444_to_rgba8888(data: &[u8], width: u32, height: u3
     }
 
     let pixel_count = (width * height) as usize;
-    let mut result: Vec<u8> = Vec::with_capacity(pixel_count * 4);
-    result.resize(pixel_count * 4, 0);
+    let mut result: Vec<u8> = vec![0; pixel_count * 4];
 
     for i in 0..pixel_count {
         let index = i * 2;
@@ -45,10 +44,52 @@ pub fn convert_image_bgra8888_to_rgba8888(data: Vec<u8>) -> Vec<u8> {
     result
 }
 
+pub fn decompress_image_dxt3_to_rgba8888(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut result = decompress_image_dxt_to_rgba8888(data, width, height, Format::Bc2);
+
+    // squish drops the low bits of every second alpha value, so they are read again from the
+    // 4 bit values at the start of each block
+    let (width, height) = (width as usize, height as usize);
+    let blocks_wide = width.div_ceil(4);
+    for y in 0..height {
+        for x in 0..width {
+            let block_offset = ((y / 4) * blocks_wide + x / 4) * 16;
+            let index = (y % 4) * 4 + x % 4;
+            let alpha = (data[block_offset + index / 2] >> (4 * (index % 2))) & 0x0F;
+            result[(y * width + x) * 4 + 3] = alpha * 17;
+        }
+    }
+
+    result
+}
+
 pub fn decompress_image_dxt5_to_rgba8888(data: &[u8], width: u32, height: u32) -> Vec<u8> {
-    let mut result = vec![0u8; (4 * width * height) as usize];
+    decompress_image_dxt_to_rgba8888(data, width, height, Format::Bc3)
+}
 
-    Format::Bc3.decompress(data, width as usize, height as usize, &mut result);
+// squish writes past its output when the last row of 4x4 blocks is partial, so the blocks are
+// decompressed at the padded size and cropped afterwards
+fn decompress_image_dxt_to_rgba8888(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: Format,
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (padded_width, padded_height) = (width.div_ceil(4) * 4, height.div_ceil(4) * 4);
+    let mut padded = vec![0u8; 4 * padded_width * padded_height];
+
+    format.decompress(data, padded_width, padded_height, &mut padded);
+
+    if padded_width == width {
+        padded.truncate(4 * width * height);
+        return padded;
+    }
+
+    let mut result = Vec::with_capacity(4 * width * height);
+    for row in padded.chunks_exact(4 * padded_width).take(height) {
+        result.extend_from_slice(&row[..4 * width]);
+    }
 
     result
 }
@@ -80,3 +121,106 @@ pub fn decompress_image_bgr565_to_rgba8888(data: &[u8], width: u32, height: u32)
 
     result
 }
+
+// Format 517 stores a single bgr565 color for every 16x16 block, the last row and column of
+// blocks are partial when the size is not a multiple of 16
+pub fn decompress_image_bgr565_block16_to_rgba8888(
+    data: &[u8],
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    let blocks_wide = width.div_ceil(16) as usize;
+    let blocks = decompress_image_bgr565_to_rgba8888(data, width.div_ceil(16), height.div_ceil(16));
+    let mut result = vec![0u8; (4 * width * height) as usize];
+
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let block_index = ((y / 16) * blocks_wide + x / 16) * 4;
+            let output_index = (y * width as usize + x) * 4;
+            result[output_index..output_index + 4]
+                .copy_from_slice(&blocks[block_index..block_index + 4]);
+        }
+    }
+
+    result
+}
+
+pub fn compress_image_rgba8888_to_bgra4444(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() / 2);
+
+    for pixel in data.chunks_exact(4) {
+        // Round to the nearest 4 bit value
+        let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]].map(|c| (c as u16 + 8) / 17);
+        result.push((b | (g << 4)) as u8);
+        result.push((r | (a << 4)) as u8);
+    }
+
+    result
+}
+
+pub fn convert_image_rgba8888_to_bgra8888(data: &[u8]) -> Vec<u8> {
+    // Swapping red and blue goes both ways
+    convert_image_bgra8888_to_rgba8888(data.to_vec())
+}
+
+pub fn compress_image_rgba8888_to_bgr565(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() / 2);
+
+    for pixel in data.chunks_exact(4) {
+        let red = (pixel[0] as u16 >> 3) << 11;
+        let green = (pixel[1] as u16 >> 2) << 5;
+        let blue = pixel[2] as u16 >> 3;
+        result.extend_from_slice(&(red | green | blue).to_le_bytes());
+    }
+
+    result
+}
+
+/// Average every 16x16 block into a single bgr565 color, partial blocks at the edges included
+pub fn compress_image_rgba8888_to_bgr565_block16(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (blocks_wide, blocks_high) = (width.div_ceil(16), height.div_ceil(16));
+    let mut blocks = vec![0u8; blocks_wide * blocks_high * 4];
+
+    for block_y in 0..blocks_high {
+        for block_x in 0..blocks_wide {
+            let (rows, columns) = (
+                block_y * 16..(block_y * 16 + 16).min(height),
+                block_x * 16..(block_x * 16 + 16).min(width),
+            );
+            let pixel_count = (rows.len() * columns.len()) as u32;
+
+            let mut sum = [0u32; 3];
+            for y in rows {
+                for x in columns.clone() {
+                    let index = (y * width + x) * 4;
+                    for (channel, total) in sum.iter_mut().enumerate() {
+                        *total += data[index + channel] as u32;
+                    }
+                }
+            }
+
+            let index = (block_y * blocks_wide + block_x) * 4;
+            for (channel, total) in sum.iter().enumerate() {
+                blocks[index + channel] = (total / pixel_count) as u8;
+            }
+            blocks[index + 3] = 255;
+        }
+    }
+
+    compress_image_rgba8888_to_bgr565(&blocks)
+}
+
+pub fn compress_image_rgba8888_to_dxt(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: Format,
+    params: Params,
+) -> Vec<u8> {
+    let mut result = vec![0u8; format.compressed_size(width as usize, height as usize)];
+
+    format.compress(data, width as usize, height as usize, params, &mut result);
+
+    result
+}
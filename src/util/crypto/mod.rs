@@ -1,5 +1,7 @@
 pub mod wz_mutable_key;
 pub mod wz_key;
+pub mod wz_key_detection;
 
 pub use wz_mutable_key::*;
 pub use wz_key::*;
+pub use wz_key_detection::*;
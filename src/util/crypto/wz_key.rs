@@ -1,10 +1,15 @@
 use crate::WzMutableKey;
 
+/// No encryption, used by GMS as well as the current CMS, TMS and JMS clients
 pub const WZ_GMS_IV: [u8; 4] = [0; 4];
 
+/// Older GMS clients
 pub const WZ_GMS_OLD_IV: [u8; 4] = [0x4D, 0x23, 0xC7, 0x2B];
 
-const MAPLESTORY_AES_USERKEY_DEFAULT: [u8; 128] = [
+/// KMS and MSEA clients
+pub const WZ_KMS_IV: [u8; 4] = [0xB9, 0x7D, 0x63, 0xE9];
+
+pub const MAPLESTORY_AES_USERKEY_DEFAULT: [u8; 128] = [
     0x13, 0x00, 0x00, 0x00, 0x52, 0x00, 0x00, 0x00, 0x2A, 0x00, 0x00, 0x00, 0x5B, 0x00, 0x00, 0x00,
     0x08, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x60, 0x00, 0x00, 0x00,
     0x06, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x43, 0x00, 0x00, 0x00, 0x0F, 0x00, 0x00, 0x00,
@@ -17,13 +22,18 @@ const MAPLESTORY_AES_USERKEY_DEFAULT: [u8; 128] = [
 
 /// WZ key used to decrypt strings. In newer WZ versions, decryption is not used
 pub fn generate_wz_key(iv: [u8; 4]) -> Option<WzMutableKey> {
+    generate_wz_key_with_user_key(iv, MAPLESTORY_AES_USERKEY_DEFAULT)
+}
+
+/// Same as `generate_wz_key`, but with a custom 128-byte AES user key (e.g. private clients)
+pub fn generate_wz_key_with_user_key(iv: [u8; 4], user_key: [u8; 128]) -> Option<WzMutableKey> {
     if iv[0] == 0 && iv[1] == 0 && iv[2] == 0 && iv[3] == 0 {
         return None;
     }
 
     Some(WzMutableKey {
         iv,
-        aes_user_key: get_trimmed_user_key(MAPLESTORY_AES_USERKEY_DEFAULT),
+        aes_user_key: get_trimmed_user_key(user_key),
         key: None,
     })
 }
@@ -37,3 +47,49 @@ fn get_trimmed_user_key(user_key: [u8; 128]) -> [u8; 32] {
     }
     key
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate_wz_key, get_iv_for_version, WzReader, WzVersion};
+    use std::io::Cursor;
+
+    // `Mob.img` as stored in a directory with each key: masked, then xored with the AES key
+    // stream of the IV. Computed independently with openssl.
+    const ENCRYPTED_NAMES: [([u8; 4], [u8; 7]); 3] = [
+        ([0; 4], [0xE7, 0xC4, 0xCE, 0x83, 0xC7, 0xC2, 0xD7]),
+        (
+            [0x4D, 0x23, 0xC7, 0x2B],
+            [0x71, 0x6A, 0xF1, 0x27, 0x8F, 0x38, 0x0A],
+        ),
+        (
+            [0xB9, 0x7D, 0x63, 0xE9],
+            [0x4C, 0xA1, 0x87, 0x86, 0xA0, 0x0F, 0x80],
+        ),
+    ];
+
+    #[test]
+    fn test_decrypt_names() {
+        let versions = [
+            WzVersion::GMS,
+            WzVersion::GMS_OLD,
+            WzVersion::KMS,
+            WzVersion::CMS,
+            WzVersion::TMS,
+            WzVersion::MSEA,
+            WzVersion::JMS,
+        ];
+
+        for version in versions {
+            let iv = get_iv_for_version(version);
+            let (_, encrypted) = ENCRYPTED_NAMES
+                .iter()
+                .find(|(known_iv, _)| *known_iv == iv)
+                .unwrap_or_else(|| panic!("no known name for {:?}", version));
+
+            let mut data = vec![(-(encrypted.len() as i8)) as u8];
+            data.extend_from_slice(encrypted);
+            let reader = WzReader::new(Cursor::new(data), generate_wz_key(iv));
+            assert_eq!(reader.read_wz_string().unwrap(), "Mob.img", "{:?}", version);
+        }
+    }
+}
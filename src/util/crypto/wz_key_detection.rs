@@ -0,0 +1,105 @@
+use crate::{generate_wz_key_with_user_key, get_iv_for_version, WzReader, WzVersion};
+use std::io::Error;
+
+// Only the first few entries are needed to tell a good key from a bad one
+const MAX_SCORED_ENTRIES: i32 = 8;
+
+/// Every built-in IV, listed once per distinct IV
+pub const WZ_KNOWN_VERSIONS: [WzVersion; 3] = [WzVersion::GMS, WzVersion::GMS_OLD, WzVersion::KMS];
+
+/// Score every candidate by trial-decoding the entry names of the root directory, best first.
+/// Candidates sharing an IV are only scored once. The reader position is left untouched.
+pub fn rank_wz_versions(
+    reader: &mut WzReader,
+    candidates: &[WzVersion],
+    aes_user_key: [u8; 128],
+) -> Result<Vec<(WzVersion, u32)>, Error> {
+    let original_position = reader.get_position()?;
+    let original_key = reader.wz_mutable_key.clone();
+
+    let mut ranked: Vec<(WzVersion, u32)> = vec![];
+    for candidate in candidates {
+        let iv = get_iv_for_version(*candidate);
+        if ranked.iter().any(|(v, _)| get_iv_for_version(*v) == iv) {
+            continue;
+        }
+
+        reader.set_wz_mutable_key(generate_wz_key_with_user_key(iv, aes_user_key));
+        let score = score_wz_key(reader);
+        log::trace!("key score for {:?}: {}", candidate, score);
+        ranked.push((*candidate, score));
+    }
+
+    // Stable sort keeps the candidate order for ties
+    ranked.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    reader.set_wz_mutable_key(original_key);
+    reader.seek(original_position)?;
+
+    Ok(ranked)
+}
+
+// The root directory starts after the version header, or right at file start for
+// header-less files, so try both and keep the best
+fn score_wz_key(reader: &WzReader) -> u32 {
+    let file_start = *reader.file_start.borrow() as u64;
+    [file_start + 2, file_start]
+        .iter()
+        .map(|offset| score_directory_names(reader, *offset).unwrap_or(0))
+        .max()
+        .unwrap_or(0)
+}
+
+fn score_directory_names(reader: &WzReader, offset: u64) -> Result<u32, Error> {
+    let file_start = *reader.file_start.borrow() as u64;
+    reader.seek(offset)?;
+
+    let count = reader.read_wz_int()?;
+    if count <= 0 || count > 0xFFFF {
+        return Ok(0);
+    }
+
+    let mut score = 0;
+    for _ in 0..count.min(MAX_SCORED_ENTRIES) {
+        let mut entry_type = reader.read_u8()?;
+        let entry_name = match entry_type {
+            2 => {
+                let name_offset = reader.read_u32()?;
+                let remember_pos = reader.get_position()?;
+                reader.seek(file_start + name_offset as u64)?;
+                entry_type = reader.read_u8()?;
+                let entry_name = reader.read_wz_string();
+                reader.seek(remember_pos)?;
+                entry_name
+            }
+            3 | 4 => reader.read_wz_string(),
+            _ => break,
+        };
+
+        // A name that fails to decode leaves the reader in an unknown position
+        let Ok(entry_name) = entry_name else {
+            break;
+        };
+        score += score_entry_name(&entry_name, entry_type);
+
+        // Skip size, checksum and offset
+        reader.read_wz_int()?;
+        reader.read_wz_int()?;
+        reader.skip(4)?;
+    }
+
+    Ok(score)
+}
+
+// Printable names are worth a point, .img entries ending in ".img" are worth another
+fn score_entry_name(name: &str, entry_type: u8) -> u32 {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        return 0;
+    }
+
+    if entry_type == 4 && name.ends_with(".img") {
+        2
+    } else {
+        1
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{join_path, ArcWzNode, WzNode, WzValue};
+use indexmap::IndexSet;
+use std::{
+    fs::File,
+    io::{Result, Write},
+};
+
+/// Union of the value paths of every child, in the order they are first seen, e.g. `info/price`.
+/// Canvas and sound subtrees are skipped.
+pub fn get_csv_columns(node: &ArcWzNode) -> Vec<String> {
+    let mut columns = IndexSet::new();
+    for child in node.children.values() {
+        collect_columns(child, "", &mut columns);
+    }
+    columns.into_iter().collect()
+}
+
+/// Export a node whose children share a schema as CSV, one row per child. The first column is
+/// the child name, see `get_csv_key_column`, missing values are left empty.
+pub fn to_csv(node: &ArcWzNode) -> Result<String> {
+    let columns = get_csv_columns(node);
+    let mut csv = vec![];
+    write_csv(&mut csv, node, &columns)?;
+    Ok(String::from_utf8_lossy(&csv).into_owned())
+}
+
+/// Name of the column holding the child names: `id`, with leading underscores when a value
+/// column already has that name
+pub fn get_csv_key_column(columns: &[String]) -> String {
+    let mut key_column = "id".to_string();
+    while columns.contains(&key_column) {
+        key_column.insert(0, '_');
+    }
+    key_column
+}
+
+/// Same as `to_csv`, with an explicit list of columns
+pub fn write_csv<W: Write>(writer: &mut W, node: &ArcWzNode, columns: &[String]) -> Result<()> {
+    let key_column = get_csv_key_column(columns);
+    let header = std::iter::once(key_column.as_str()).chain(columns.iter().map(String::as_str));
+    write_csv_row(writer, header)?;
+
+    for (name, child) in &node.children {
+        let values = columns
+            .iter()
+            .map(|column| get_csv_value(child, column).unwrap_or_default())
+            .collect::<Vec<_>>();
+        let row = std::iter::once(name.as_str()).chain(values.iter().map(String::as_str));
+        write_csv_row(writer, row)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_csv_to_file(csv: &str, output_file: &str) -> Result<()> {
+    let mut file = File::create(output_file)?;
+    file.write_all(csv.as_bytes())?;
+    Ok(())
+}
+
+fn collect_columns(node: &WzNode, path: &str, columns: &mut IndexSet<String>) {
+    for (name, child) in &node.children {
+        let child_path = join_path(path, name);
+
+        match &child.value {
+            WzValue::Canvas(_) | WzValue::Sound(_) => {}
+            _ if !child.children.is_empty() => collect_columns(child, &child_path, columns),
+            value if format_csv_value(value).is_some() => {
+                columns.insert(child_path);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn get_csv_value(node: &WzNode, path: &str) -> Option<String> {
+    let mut current = node;
+    for name in path.split('/') {
+        current = current.children.get(name)?;
+    }
+    format_csv_value(&current.value)
+}
+
+fn format_csv_value(value: &WzValue) -> Option<String> {
+    match value {
+        WzValue::Short(val) => Some(val.to_string()),
+        WzValue::Int(val) => Some(val.to_string()),
+        WzValue::Long(val) => Some(val.to_string()),
+        WzValue::Float(val) => Some(val.to_string()),
+        WzValue::Double(val) => Some(val.to_string()),
+        WzValue::String(val) | WzValue::Uol(val) => Some(val.clone()),
+        WzValue::Vector(val) => Some(format!("{},{}", val.x, val.y)),
+        _ => None,
+    }
+}
+
+// Quote fields containing separators, quotes or line breaks (RFC 4180)
+fn write_csv_row<'a, W: Write>(
+    writer: &mut W,
+    fields: impl Iterator<Item = &'a str>,
+) -> Result<()> {
+    let row = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    writer.write_all(row.as_bytes())?;
+    writer.write_all(b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_tree::{leaf, node},
+        Vec2, WzCanvas,
+    };
+
+    fn item(name: &str, children: Vec<ArcWzNode>) -> ArcWzNode {
+        node(name, WzValue::Extended, children)
+    }
+
+    #[test]
+    fn test_quoting() {
+        let items = item(
+            "items",
+            vec![
+                item(
+                    "1000",
+                    vec![leaf("name", WzValue::String("a, b".to_string()))],
+                ),
+                item(
+                    "1001",
+                    vec![leaf("name", WzValue::String("say \"hi\"".to_string()))],
+                ),
+                item(
+                    "1002",
+                    vec![leaf("name", WzValue::String("two\nlines".to_string()))],
+                ),
+            ],
+        );
+
+        assert_eq!(
+            to_csv(&items).unwrap(),
+            "id,name\r\n1000,\"a, b\"\r\n1001,\"say \"\"hi\"\"\"\r\n1002,\"two\nlines\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_columns() {
+        let items = item(
+            "items",
+            vec![
+                item(
+                    "1000",
+                    vec![
+                        item("info", vec![leaf("price", WzValue::Int(10))]),
+                        leaf("pos", WzValue::Vector(Vec2 { x: -1, y: 2 })),
+                        node(
+                            "icon",
+                            WzValue::Canvas(WzCanvas::default()),
+                            vec![leaf("origin", WzValue::Vector(Vec2 { x: 0, y: 0 }))],
+                        ),
+                    ],
+                ),
+                item("1001", vec![leaf("slotMax", WzValue::Short(100))]),
+            ],
+        );
+
+        assert_eq!(get_csv_columns(&items), ["info/price", "pos", "slotMax"]);
+        assert_eq!(
+            to_csv(&items).unwrap(),
+            "id,info/price,pos,slotMax\r\n1000,10,\"-1,2\",\r\n1001,,,100\r\n"
+        );
+    }
+
+    #[test]
+    fn test_key_column() {
+        assert_eq!(get_csv_key_column(&["name".to_string()]), "id");
+        assert_eq!(
+            get_csv_key_column(&["id".to_string(), "_id".to_string()]),
+            "__id"
+        );
+
+        let items = item(
+            "items",
+            vec![item("1000", vec![leaf("id", WzValue::Int(7))])],
+        );
+        assert_eq!(to_csv(&items).unwrap(), "_id,id\r\n1000,7\r\n");
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{join_path, parse_canvas, parse_sound_buffer, ArcWzNode, WzReader, WzValue};
+use serde::Serialize;
+use std::{
+    fmt,
+    io::{Error, Result},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum WzChange {
+    Added { value: String },
+    Removed { value: String },
+    Modified { old: String, new: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WzDiffEntry {
+    pub path: String,
+    #[serde(flatten)]
+    pub change: WzChange,
+}
+
+impl fmt::Display for WzDiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.change {
+            WzChange::Added { value } => write!(f, "+ {} ({})", self.path, value),
+            WzChange::Removed { value } => write!(f, "- {} ({})", self.path, value),
+            WzChange::Modified { old, new } => write!(f, "~ {}: {} -> {}", self.path, old, new),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct WzDiff {
+    pub entries: Vec<WzDiffEntry>,
+}
+
+impl WzDiff {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = &WzDiffEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.change, WzChange::Added { .. }))
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &WzDiffEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.change, WzChange::Removed { .. }))
+    }
+
+    pub fn modified(&self) -> impl Iterator<Item = &WzDiffEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.change, WzChange::Modified { .. }))
+    }
+
+    /// Patch notes style report, grouped by added, removed and modified
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (title, entries) in [
+            ("Added", self.added().collect::<Vec<_>>()),
+            ("Removed", self.removed().collect()),
+            ("Modified", self.modified().collect()),
+        ] {
+            if entries.is_empty() {
+                continue;
+            }
+
+            text.push_str(&format!("{} ({})\n", title, entries.len()));
+            for entry in entries {
+                text.push_str(&format!("  {}\n", entry));
+            }
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(Error::other)
+    }
+}
+
+/// Compare two trees, e.g. the same .img from two patches. Paths start below the given nodes.
+/// Added and removed subtrees are reported once at their root. With readers, canvases are
+/// compared by a hash of their pixels and sounds by one of their buffer, otherwise only by their
+/// metadata.
+/// Unparsed .img nodes are compared by their directory entry.
+pub fn diff_nodes(
+    old: &ArcWzNode,
+    old_reader: Option<Arc<WzReader>>,
+    new: &ArcWzNode,
+    new_reader: Option<Arc<WzReader>>,
+) -> WzDiff {
+    let mut diff = WzDiff::default();
+    diff_children(
+        old,
+        old_reader.as_ref(),
+        new,
+        new_reader.as_ref(),
+        "",
+        &mut diff,
+    );
+    diff
+}
+
+fn diff_children(
+    old: &ArcWzNode,
+    old_reader: Option<&Arc<WzReader>>,
+    new: &ArcWzNode,
+    new_reader: Option<&Arc<WzReader>>,
+    path: &str,
+    diff: &mut WzDiff,
+) {
+    for (name, old_child) in &old.children {
+        let child_path = join_path(path, name);
+        match new.children.get(name) {
+            Some(new_child) => diff_node(
+                old_child,
+                old_reader,
+                new_child,
+                new_reader,
+                &child_path,
+                diff,
+            ),
+            None => diff.entries.push(WzDiffEntry {
+                path: child_path,
+                change: WzChange::Removed {
+                    value: describe_value(old_child, old_reader),
+                },
+            }),
+        }
+    }
+
+    for (name, new_child) in &new.children {
+        if !old.children.contains_key(name) {
+            diff.entries.push(WzDiffEntry {
+                path: join_path(path, name),
+                change: WzChange::Added {
+                    value: describe_value(new_child, new_reader),
+                },
+            });
+        }
+    }
+}
+
+fn diff_node(
+    old: &ArcWzNode,
+    old_reader: Option<&Arc<WzReader>>,
+    new: &ArcWzNode,
+    new_reader: Option<&Arc<WzReader>>,
+    path: &str,
+    diff: &mut WzDiff,
+) {
+    let old_value = describe_value(old, old_reader);
+    let new_value = describe_value(new, new_reader);
+    if old_value != new_value {
+        diff.entries.push(WzDiffEntry {
+            path: path.to_string(),
+            change: WzChange::Modified {
+                old: old_value,
+                new: new_value,
+            },
+        });
+    }
+
+    diff_children(old, old_reader, new, new_reader, path, diff);
+}
+
+// Compact description used both to compare values and in the report
+fn describe_value(node: &ArcWzNode, reader: Option<&Arc<WzReader>>) -> String {
+    match &node.value {
+        WzValue::Img if node.children.is_empty() => match node.entry {
+            Some(entry) => format!("Img: size {} checksum {}", entry.size, entry.checksum),
+            None => node.value.to_string(),
+        },
+        WzValue::Canvas(canvas) => {
+            // The same pixels can be compressed differently, so the decoded ones are hashed
+            let hash = reader.and_then(|reader| {
+                parse_canvas(canvas, reader.clone())
+                    .ok()
+                    .map(|image| hash_bytes(&image.data))
+            });
+            // Offsets move between patches, so they are left out
+            let description = format!(
+                "Canvas: {}x{} format {}",
+                canvas.width,
+                canvas.height,
+                canvas.format1 + canvas.format2 as u32
+            );
+            match hash {
+                Some(hash) => format!("{} #{:016x}", description, hash),
+                None => description,
+            }
+        }
+        WzValue::Sound(sound) => {
+            let hash = reader.and_then(|reader| {
+                parse_sound_buffer(sound, reader.clone())
+                    .ok()
+                    .map(|buffer| hash_bytes(&buffer))
+            });
+            match hash {
+                Some(hash) => format!("Sound: {}ms #{:016x}", sound.duration, hash),
+                None => format!("Sound: {}ms {} bytes", sound.duration, sound.buffer_size),
+            }
+        }
+        value => value.to_string(),
+    }
+}
+
+// FNV-1a, stable across runs so reports can be compared
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_tree::{leaf, node, test_image},
+        write_canvas_payload, WzCanvas,
+    };
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::{Cursor, Write};
+
+    // A bgra8888 canvas, compressed at the given level
+    fn canvas(payloads: &mut Vec<u8>, seed: u8, compression: Compression) -> WzValue {
+        let mut image = test_image(4, 4);
+        image.data[0] = seed;
+        let mut encoder = ZlibEncoder::new(vec![], compression);
+        encoder.write_all(&image.data).unwrap();
+
+        WzValue::Canvas(WzCanvas {
+            width: image.width,
+            height: image.height,
+            format1: 2,
+            offset: write_canvas_payload(payloads, &encoder.finish().unwrap()),
+            ..Default::default()
+        })
+    }
+
+    fn paths<'a>(entries: impl Iterator<Item = &'a WzDiffEntry>) -> Vec<&'a str> {
+        entries.map(|entry| entry.path.as_str()).collect()
+    }
+
+    fn reader(payloads: Vec<u8>) -> Option<Arc<WzReader>> {
+        Some(WzReader::new(Cursor::new(payloads), None).into())
+    }
+
+    #[test]
+    fn test_diff_nodes() {
+        let mut old_payloads = vec![];
+        let old = node(
+            "0100100.img",
+            WzValue::Img,
+            vec![
+                leaf("speed", WzValue::Int(10)),
+                leaf("name", WzValue::String("Snail".to_string())),
+                node(
+                    "stand",
+                    WzValue::Extended,
+                    vec![
+                        leaf("0", canvas(&mut old_payloads, 0, Compression::none())),
+                        leaf("1", canvas(&mut old_payloads, 0, Compression::none())),
+                    ],
+                ),
+            ],
+        );
+
+        let mut new_payloads = vec![];
+        let new = node(
+            "0100100.img",
+            WzValue::Img,
+            vec![
+                leaf("speed", WzValue::Int(20)),
+                node(
+                    "stand",
+                    WzValue::Extended,
+                    vec![
+                        leaf("0", canvas(&mut new_payloads, 0, Compression::best())),
+                        leaf("1", canvas(&mut new_payloads, 1, Compression::none())),
+                    ],
+                ),
+                node(
+                    "info",
+                    WzValue::Extended,
+                    vec![leaf("level", WzValue::Int(1))],
+                ),
+            ],
+        );
+
+        let diff = diff_nodes(&old, reader(old_payloads), &new, reader(new_payloads));
+        assert_eq!(paths(diff.added()), ["info"]);
+        assert_eq!(paths(diff.removed()), ["name"]);
+        // stand/0 only differs in its compression
+        assert_eq!(paths(diff.modified()), ["speed", "stand/1"]);
+        assert_eq!(
+            diff.entries[0].change,
+            WzChange::Modified {
+                old: "Int: 10".to_string(),
+                new: "Int: 20".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+};
+
+/// The PKG1 header at the start of every .wz file
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct WzHeader {
+    pub ident: String,
+    /// Declared size of the data following the header
+    pub size: u64,
+    pub file_start: u32,
+    pub copyright: String,
+    /// The version hash checksum stored at `file_start`, `None` until the layout is known and
+    /// for header-less files
+    pub encrypted_version: Option<u16>,
+}
+
+impl WzHeader {
+    pub const IDENT: &'static str = "PKG1";
+
+    /// Check the declared size against the real length of the file
+    pub fn validate_size(&self, file_len: u64) -> Result<(), Error> {
+        let expected_len = self.size + self.file_start as u64;
+        if expected_len != file_len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Declared size does not match the file length (expected {}, actual {})",
+                    expected_len, file_len
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for WzHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WzHeader(ident: {}, size: {}, file_start: {}, copyright: {}, encrypted_version: {:?})",
+            self.ident, self.size, self.file_start, self.copyright, self.encrypted_version
+        )
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{ArcWzNode, WzNode, WzReader, WzValueCast};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WzIntegrityIssue {
+    /// The declared size runs past the end of the file
+    OutOfBounds {
+        size: i32,
+        file_len: usize,
+    },
+    ChecksumMismatch {
+        expected: i32,
+        actual: i32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WzIntegrityError {
+    pub path: String,
+    pub offset: usize,
+    pub issue: WzIntegrityIssue,
+}
+
+impl fmt::Display for WzIntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.issue {
+            WzIntegrityIssue::OutOfBounds { size, file_len } => write!(
+                f,
+                "{} at {}: size {} is out of bounds (file length {})",
+                self.path, self.offset, size, file_len
+            ),
+            WzIntegrityIssue::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "{} at {}: checksum mismatch (expected {}, actual {})",
+                self.path, self.offset, expected, actual
+            ),
+        }
+    }
+}
+
+/// Recompute the checksum of a .img from its bytes, which is the sum of every byte
+pub fn calculate_img_checksum(reader: &WzReader, offset: usize, size: usize) -> Option<i32> {
+    let file = reader.file.borrow();
+    let bytes = file.get_ref().get(offset..offset.checked_add(size)?)?;
+
+    Some(
+        bytes
+            .iter()
+            .fold(0i32, |checksum, byte| checksum.wrapping_add(*byte as i32)),
+    )
+}
+
+/// Check a single .img against the size and checksum declared by its directory.
+/// Nodes without a directory entry are always valid.
+pub fn verify_img(node: &WzNode, reader: &WzReader) -> Result<(), WzIntegrityIssue> {
+    let Some(entry) = node.entry else {
+        return Ok(());
+    };
+
+    let file_len = reader.file.borrow().get_ref().len();
+    let actual = calculate_img_checksum(reader, node.offset, entry.size.max(0) as usize).ok_or(
+        WzIntegrityIssue::OutOfBounds {
+            size: entry.size,
+            file_len,
+        },
+    )?;
+
+    if actual != entry.checksum {
+        return Err(WzIntegrityIssue::ChecksumMismatch {
+            expected: entry.checksum,
+            actual,
+        });
+    }
+
+    Ok(())
+}
+
+/// Verify every .img under this node, returning the ones that failed
+pub fn check_integrity(node: &ArcWzNode, reader: &WzReader) -> Vec<WzIntegrityError> {
+    let mut errors = vec![];
+    check_integrity_recursive(node, &node.name, reader, &mut errors);
+    errors
+}
+
+fn check_integrity_recursive(
+    node: &ArcWzNode,
+    path: &str,
+    reader: &WzReader,
+    errors: &mut Vec<WzIntegrityError>,
+) {
+    if node.value.is_img() {
+        if let Err(issue) = verify_img(node, reader) {
+            log::warn!("integrity check failed for {}: {:?}", path, issue);
+            errors.push(WzIntegrityError {
+                path: path.to_string(),
+                offset: node.offset,
+                issue,
+            });
+        }
+        return;
+    }
+
+    if node.value.is_directory() {
+        for (name, child) in &node.children {
+            check_integrity_recursive(child, &format!("{}/{}", path, name), reader, errors);
+        }
+    }
+}
@@ -1,11 +1,11 @@
 use crate::ArcWzNode;
 use std::{
     fs::File,
-    io::{Error, ErrorKind, Result, Write},
+    io::{Error, Result, Write},
 };
 
 pub fn to_json(node: &ArcWzNode) -> Result<String> {
-    serde_json::to_string_pretty(node.as_ref()).map_err(|e| Error::new(ErrorKind::Other, e))
+    serde_json::to_string_pretty(node.as_ref()).map_err(Error::other)
 }
 
 pub fn write_json_to_file(json: &str, output_file: &str) -> Result<()> {
@@ -0,0 +1,284 @@
+use crate::{
+    parse_canvas_buffer, parse_sound_buffer, parse_sound_format, parse_sound_header,
+    write_canvas_payload, ArcWzNode, Vec2, WzCanvas, WzEntry, WzNode, WzReader, WzSound, WzValue,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Cursor, Error, ErrorKind, Result},
+    sync::Arc,
+};
+
+#[derive(Serialize, Deserialize)]
+struct JsonNode {
+    name: String,
+    offset: usize,
+    #[serde(flatten)]
+    value: JsonValue,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entry: Option<WzEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    children: Vec<JsonNode>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+enum JsonValue {
+    Null,
+    Directory,
+    Img,
+    Extended,
+    Convex,
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Vector(Vec2),
+    Canvas(JsonCanvas),
+    Sound(JsonSound),
+    Uol(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonCanvas {
+    width: u32,
+    height: u32,
+    format1: u32,
+    format2: u8,
+    offset: u32,
+    origin: Vec2,
+    /// Base64 of the payload as stored in the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonSound {
+    name: String,
+    duration: u32,
+    header_offset: u64,
+    header_size: usize,
+    buffer_offset: u64,
+    buffer_size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    buffer: Option<String>,
+}
+
+/// Export a node to JSON where every value is tagged with its type, so it can be read back with
+/// `from_lossless_json`. With a reader, canvas and sound payloads are embedded as base64.
+pub fn to_lossless_json(node: &ArcWzNode, reader: Option<Arc<WzReader>>) -> Result<String> {
+    let json_node = to_json_node(node, reader.as_ref())?;
+    serde_json::to_string_pretty(&json_node).map_err(Error::other)
+}
+
+/// Rebuild a tree from `to_lossless_json` output. Embedded payloads are packed into the returned
+/// reader, so `parse_canvas` and `parse_sound_buffer` work on the rebuilt tree. Without payloads
+/// the offsets still point into the original file.
+pub fn from_lossless_json(json: &str) -> Result<(ArcWzNode, Arc<WzReader>)> {
+    let json_node: JsonNode =
+        serde_json::from_str(json).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    let mut payloads = vec![];
+    let node = from_json_node(json_node, &mut payloads)?;
+    let reader = WzReader::new(Cursor::new(payloads), None);
+
+    Ok((node, reader.into()))
+}
+
+fn to_json_node(node: &ArcWzNode, reader: Option<&Arc<WzReader>>) -> Result<JsonNode> {
+    let value = match &node.value {
+        WzValue::Null => JsonValue::Null,
+        WzValue::Directory => JsonValue::Directory,
+        WzValue::Img => JsonValue::Img,
+        WzValue::Extended => JsonValue::Extended,
+        WzValue::Convex => JsonValue::Convex,
+        WzValue::Short(val) => JsonValue::Short(*val),
+        WzValue::Int(val) => JsonValue::Int(*val),
+        WzValue::Long(val) => JsonValue::Long(*val),
+        WzValue::Float(val) => JsonValue::Float(*val),
+        WzValue::Double(val) => JsonValue::Double(*val),
+        WzValue::String(val) => JsonValue::String(val.clone()),
+        WzValue::Vector(val) => JsonValue::Vector(val.clone()),
+        WzValue::Canvas(canvas) => {
+            let data = match reader {
+                Some(reader) => Some(STANDARD.encode(parse_canvas_buffer(canvas, reader.clone())?)),
+                None => None,
+            };
+
+            JsonValue::Canvas(JsonCanvas {
+                width: canvas.width,
+                height: canvas.height,
+                format1: canvas.format1,
+                format2: canvas.format2,
+                offset: canvas.offset,
+                origin: canvas.origin.clone(),
+                data,
+            })
+        }
+        WzValue::Sound(sound) => {
+            let (header, buffer) = match reader {
+                Some(reader) => (
+                    Some(STANDARD.encode(parse_sound_header(sound, reader.clone())?)),
+                    Some(STANDARD.encode(parse_sound_buffer(sound, reader.clone())?)),
+                ),
+                None => (None, None),
+            };
+
+            JsonValue::Sound(JsonSound {
+                name: sound.name.clone(),
+                duration: sound.duration,
+                header_offset: sound.header_offset,
+                header_size: sound.header_size,
+                buffer_offset: sound.buffer_offset,
+                buffer_size: sound.buffer_size,
+                header,
+                buffer,
+            })
+        }
+        WzValue::Uol(val) => JsonValue::Uol(val.clone()),
+    };
+
+    let children = node
+        .children
+        .values()
+        .map(|child| to_json_node(child, reader))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(JsonNode {
+        name: node.name.clone(),
+        offset: node.offset,
+        value,
+        entry: node.entry,
+        children,
+    })
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(data)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+fn from_json_node(json_node: JsonNode, payloads: &mut Vec<u8>) -> Result<ArcWzNode> {
+    let value = match json_node.value {
+        JsonValue::Null => WzValue::Null,
+        JsonValue::Directory => WzValue::Directory,
+        JsonValue::Img => WzValue::Img,
+        JsonValue::Extended => WzValue::Extended,
+        JsonValue::Convex => WzValue::Convex,
+        JsonValue::Short(val) => WzValue::Short(val),
+        JsonValue::Int(val) => WzValue::Int(val),
+        JsonValue::Long(val) => WzValue::Long(val),
+        JsonValue::Float(val) => WzValue::Float(val),
+        JsonValue::Double(val) => WzValue::Double(val),
+        JsonValue::String(val) => WzValue::String(val),
+        JsonValue::Vector(val) => WzValue::Vector(val),
+        JsonValue::Canvas(canvas) => {
+            let mut offset = canvas.offset;
+            if let Some(data) = canvas.data {
+                offset = write_canvas_payload(payloads, &decode_base64(&data)?);
+            }
+
+            WzValue::Canvas(WzCanvas {
+                width: canvas.width,
+                height: canvas.height,
+                format1: canvas.format1,
+                format2: canvas.format2,
+                offset,
+                origin: canvas.origin,
+            })
+        }
+        JsonValue::Sound(sound) => {
+            let mut header_offset = sound.header_offset;
+            let mut header_size = sound.header_size;
+            let mut format = None;
+            if let Some(header) = sound.header {
+                let header = decode_base64(&header)?;
+                header_offset = payloads.len() as u64;
+                header_size = header.len();
+                format = parse_sound_format(&header, None);
+                payloads.extend_from_slice(&header);
+            }
+
+            let mut buffer_offset = sound.buffer_offset;
+            let mut buffer_size = sound.buffer_size;
+            if let Some(buffer) = sound.buffer {
+                let buffer = decode_base64(&buffer)?;
+                buffer_offset = payloads.len() as u64;
+                buffer_size = buffer.len();
+                payloads.extend_from_slice(&buffer);
+            }
+
+            WzValue::Sound(WzSound {
+                name: sound.name,
+                duration: sound.duration,
+                header_offset,
+                header_size,
+                buffer_offset,
+                buffer_size,
+                format,
+            })
+        }
+        JsonValue::Uol(val) => WzValue::Uol(val),
+    };
+
+    let mut children = IndexMap::new();
+    for child in json_node.children {
+        let child = from_json_node(child, payloads)?;
+        children.insert(child.name.clone(), child);
+    }
+
+    let mut node = WzNode::new_with_children(&json_node.name, json_node.offset, value, children);
+    node.entry = json_node.entry;
+
+    Ok(Arc::new(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diff_nodes, resolve, test_tree::test_tree};
+
+    #[test]
+    fn test_round_trip() {
+        let (root, reader) = test_tree();
+        let json = to_lossless_json(&root, Some(reader.clone())).unwrap();
+        let (rebuilt, rebuilt_reader) = from_lossless_json(&json).unwrap();
+
+        let diff = diff_nodes(
+            &root,
+            Some(reader.clone()),
+            &rebuilt,
+            Some(rebuilt_reader.clone()),
+        );
+        assert!(diff.is_empty(), "{}", diff.to_text());
+
+        // The diff only compares sound buffers
+        let sound_path = "0100100.img/sound";
+        let (WzValue::Sound(sound), WzValue::Sound(rebuilt_sound)) = (
+            &resolve(&root, sound_path).unwrap().value,
+            &resolve(&rebuilt, sound_path).unwrap().value,
+        ) else {
+            panic!("expected sounds at {}", sound_path);
+        };
+        assert_eq!(
+            parse_sound_header(sound, reader).unwrap(),
+            parse_sound_header(rebuilt_sound, rebuilt_reader).unwrap()
+        );
+        assert_eq!(sound.format, rebuilt_sound.format);
+    }
+
+    #[test]
+    fn test_round_trip_without_payloads() {
+        let (root, _) = test_tree();
+        let json = to_lossless_json(&root, None).unwrap();
+        let (rebuilt, _) = from_lossless_json(&json).unwrap();
+
+        assert!(diff_nodes(&root, None, &rebuilt, None).is_empty());
+    }
+}
@@ -1,15 +1,39 @@
+pub mod atlas;
 pub mod color;
 pub mod crypto;
+pub mod csv;
+pub mod diff;
+pub mod header;
+pub mod integrity;
 pub mod json;
+pub mod lossless_json;
 pub mod parser;
+pub mod path;
 pub mod reader;
+pub mod search;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(test)]
+pub(crate) mod test_tree;
 pub mod uol;
 pub mod version;
+pub mod xml;
 
+pub use atlas::*;
 pub use color::*;
 pub use crypto::*;
+pub use csv::*;
+pub use diff::*;
+pub use header::*;
+pub use integrity::*;
 pub use json::*;
+pub use lossless_json::*;
 pub use parser::*;
+pub use path::*;
 pub use reader::*;
+pub use search::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
 pub use uol::*;
 pub use version::*;
+pub use xml::*;
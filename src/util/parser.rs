@@ -1,23 +1,50 @@
-use crate::{ArcWzNode, Vec2, WzCanvas, WzNode, WzReader, WzSound, WzValue, WzValueCast};
+use crate::{
+    parse_sound_format, ArcWzNode, Vec2, WzCanvas, WzEntry, WzHeader, WzLayout, WzNode, WzReader,
+    WzSound, WzValue, WzValueCast,
+};
 use indexmap::IndexMap;
 use std::{
     io::{Error, ErrorKind},
     sync::Arc,
 };
 
-/// Parse the header for a .wz file. Get the file start for the reader.
-pub fn parse_wz_header(reader: &WzReader) -> Result<u32, Error> {
+/// Parse the header for a .wz file. Leaves the reader at the file start. The encrypted version
+/// depends on the layout, so it is left to `read_encrypted_version`.
+pub fn parse_wz_header(reader: &WzReader) -> Result<WzHeader, Error> {
     let ident = reader.read_string(4)?;
 
-    if ident != "PKG1" {
-        return Err(Error::new(ErrorKind::Other, "Invalid .wz file"));
+    if ident != WzHeader::IDENT {
+        return Err(Error::other("Invalid .wz file"));
     }
 
-    let _size = reader.read_u64()?;
-    let start = reader.read_u32()?;
-    let _copyright = reader.read_string_to_end()?;
+    let size = reader.read_u64()?;
+    let file_start = reader.read_u32()?;
+    let copyright = reader.read_string_to_end()?;
+
+    reader.seek(file_start as u64)?;
 
-    Ok(start)
+    Ok(WzHeader {
+        ident,
+        size,
+        file_start,
+        copyright,
+        encrypted_version: None,
+    })
+}
+
+/// Read the encrypted version at the file start, header-less files have none.
+/// Leaves the reader at the file start.
+pub fn read_encrypted_version(reader: &WzReader, layout: WzLayout) -> Result<Option<u16>, Error> {
+    if layout == WzLayout::NoVersionHeader {
+        return Ok(None);
+    }
+
+    let file_start = *reader.file_start.borrow() as u64;
+    reader.seek(file_start)?;
+    let encrypted_version = reader.read_u16()?;
+    reader.seek(file_start)?;
+
+    Ok(Some(encrypted_version))
 }
 
 pub fn parse_directory(
@@ -26,6 +53,15 @@ pub fn parse_directory(
     name: String,
     level: usize,
 ) -> Result<ArcWzNode, Error> {
+    Ok(Arc::new(parse_directory_node(reader, offset, name, level)?))
+}
+
+fn parse_directory_node(
+    reader: &Arc<WzReader>,
+    offset: usize,
+    name: String,
+    level: usize,
+) -> Result<WzNode, Error> {
     let mut children = IndexMap::new();
 
     reader.seek(offset as u64)?;
@@ -60,8 +96,10 @@ pub fn parse_directory(
         reader.seek(remember_pos)?;
 
         // Fetch some additional info
-        let _entry_fsize = reader.read_wz_int()?;
-        let _entry_checksum = reader.read_wz_int()?;
+        let entry = WzEntry {
+            size: reader.read_wz_int()?,
+            checksum: reader.read_wz_int()?,
+        };
         let entry_offset = reader.read_wz_offset()?;
 
         // Build directories and .imgs
@@ -69,48 +107,52 @@ pub fn parse_directory(
             3 => {
                 if level > 0 {
                     let remember_pos = reader.get_position()?;
-                    if let Ok(node) = parse_directory(
+                    if let Ok(node) = parse_directory_node(
                         reader,
                         entry_offset as usize,
                         entry_name.clone(),
                         level - 1,
                     ) {
-                        children.insert(entry_name.clone(), node);
+                        children.insert(entry_name.clone(), Arc::new(node.with_entry(entry)));
                     }
                     reader.seek(remember_pos)?;
                 } else {
-                    let node = Arc::new(WzNode::new(
-                        &entry_name,
-                        entry_offset as usize,
-                        WzValue::Directory,
-                    ));
-                    children.insert(entry_name.clone(), node);
+                    let node = WzNode::new(&entry_name, entry_offset as usize, WzValue::Directory)
+                        .with_entry(entry);
+                    children.insert(entry_name.clone(), Arc::new(node));
                 }
             }
             _ => {
                 if level > 0 {
                     let remember_pos = reader.get_position()?;
-                    if let Ok(node) = parse_img(reader, entry_offset as usize, entry_name.clone()) {
-                        children.insert(entry_name.clone(), node);
+                    if let Ok(node) =
+                        parse_img_node(reader, entry_offset as usize, entry_name.clone())
+                    {
+                        children.insert(entry_name.clone(), Arc::new(node.with_entry(entry)));
                     }
                     reader.seek(remember_pos)?;
                 } else {
-                    let node = Arc::new(WzNode::new(
-                        &entry_name,
-                        entry_offset as usize,
-                        WzValue::Directory,
-                    ));
-                    children.insert(entry_name.clone(), node);
+                    let node = WzNode::new(&entry_name, entry_offset as usize, WzValue::Img)
+                        .with_entry(entry);
+                    children.insert(entry_name.clone(), Arc::new(node));
                 }
             }
         }
     }
 
-    let node = WzNode::new_with_children(&name, offset, WzValue::Directory, children);
-    Ok(Arc::new(node))
+    Ok(WzNode::new_with_children(
+        &name,
+        offset,
+        WzValue::Directory,
+        children,
+    ))
 }
 
 pub fn parse_img(reader: &Arc<WzReader>, offset: usize, name: String) -> Result<ArcWzNode, Error> {
+    Ok(Arc::new(parse_img_node(reader, offset, name)?))
+}
+
+fn parse_img_node(reader: &Arc<WzReader>, offset: usize, name: String) -> Result<WzNode, Error> {
     reader.seek(offset as u64)?;
 
     // Read the first byte and check that this node is a .img
@@ -134,14 +176,14 @@ pub fn parse_img(reader: &Arc<WzReader>, offset: usize, name: String) -> Result<
 
     // Continue parsing all properties for this node
     if let Ok(children) = parse_property_list(reader, offset) {
-        Ok(Arc::new(WzNode::new_with_children(
+        Ok(WzNode::new_with_children(
             &name,
             offset,
             WzValue::Img,
             children,
-        )))
+        ))
     } else {
-        Ok(Arc::new(WzNode::new(&name, offset, WzValue::Img)))
+        Ok(WzNode::new(&name, offset, WzValue::Img))
     }
 }
 
@@ -322,7 +364,8 @@ pub fn parse_extended_property(
 
             // Determine the header len and extract the header data
             let header_size = WzSound::SOUND_HEADER.len() as u64 + 1 + wav_len as u64;
-            reader.skip(header_size as usize)?;
+            let header = reader.read_bytes(header_size)?;
+            let format = parse_sound_format(&header, reader.wz_mutable_key.as_ref());
 
             // Extract the sound data
             let buffer_offset = reader.get_position()?;
@@ -335,6 +378,7 @@ pub fn parse_extended_property(
                 header_size: header_size as usize,
                 buffer_offset,
                 buffer_size: buffer_size as usize,
+                format,
             };
 
             WzNode::new(&name, extended_property_offset, WzValue::Sound(value))
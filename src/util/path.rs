@@ -0,0 +1,24 @@
+/// Replace the characters of a node name that are not allowed in file names
+pub fn safe_file_name(name: &str) -> String {
+    // Would otherwise point to the current or the parent directory
+    if name.is_empty() || name == "." || name == ".." {
+        return "_".repeat(name.len().max(1));
+    }
+
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Append a node name to a path relative to some node, the empty path being the node itself
+pub(crate) fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
@@ -3,7 +3,6 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use std::{
     cell::RefCell,
     io::{prelude::*, Cursor, Error, ErrorKind, SeekFrom},
-    u64,
 };
 
 #[derive(Clone)]
@@ -197,7 +196,7 @@ impl WzReader {
             size *= -1;
         }
 
-        return self.read_wz_string_as_ascii(size as u32);
+        self.read_wz_string_as_ascii(size as u32)
     }
 
     pub fn read_wz_offset(&self) -> Result<u32, Error> {
@@ -206,7 +205,7 @@ impl WzReader {
 
         let mut offset = self.get_position()?;
         offset = (offset - (file_start as u64)) ^ 0xFFFFFFFF;
-        offset = offset * (version_hash as u64);
+        offset *= version_hash as u64;
         offset -= 0x581C3F6D;
         offset = rotate_left(offset as u32, (offset & 0x1F) as u8) as u64;
 
@@ -251,7 +250,7 @@ impl WzReader {
             // Newer versions do not use encryption
             let key = self.wz_mutable_key.clone();
             if let Some(mut key) = key {
-                encrypted_char ^= key.at(i) as u8
+                encrypted_char ^= key.at(i)
             }
 
             res_string.push(encrypted_char);
@@ -266,5 +265,5 @@ impl WzReader {
 }
 
 fn rotate_left(x: u32, n: u8) -> u32 {
-    ((x) << (n)) | ((x) >> (32 - (n)))
+    x.rotate_left(n as u32)
 }
@@ -0,0 +1,103 @@
+use crate::{join_path, walk_depth_first, ArcWzNode, WzValue};
+use regex::Regex;
+use std::{ops::RangeInclusive, thread};
+
+pub enum WzValueQuery {
+    /// Exact match on a string or uol, or on the decimal form of an integer
+    Literal(String),
+    /// Matches strings, uols and the decimal form of integers
+    Regex(Regex),
+    /// Matches short, int and long values in the range
+    Range(RangeInclusive<i64>),
+}
+
+impl WzValueQuery {
+    pub fn matches(&self, value: &WzValue) -> bool {
+        match (self, value) {
+            (WzValueQuery::Range(range), value) => {
+                get_integer(value).is_some_and(|val| range.contains(&val))
+            }
+            (query, WzValue::String(val) | WzValue::Uol(val)) => query.matches_str(val),
+            (query, value) => {
+                get_integer(value).is_some_and(|val| query.matches_str(&val.to_string()))
+            }
+        }
+    }
+
+    fn matches_str(&self, value: &str) -> bool {
+        match self {
+            WzValueQuery::Literal(literal) => literal == value,
+            WzValueQuery::Regex(regex) => regex.is_match(value),
+            WzValueQuery::Range(_) => false,
+        }
+    }
+}
+
+fn get_integer(value: &WzValue) -> Option<i64> {
+    match value {
+        WzValue::Short(val) => Some(*val as i64),
+        WzValue::Int(val) => Some(*val as i64),
+        WzValue::Long(val) => Some(*val),
+        _ => None,
+    }
+}
+
+/// Find every node below this one whose value matches, returning their paths relative to it
+pub fn search_values(node: &ArcWzNode, query: &WzValueQuery) -> Vec<String> {
+    walk_depth_first(node)
+        .filter(|(_, child)| query.matches(&child.value))
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Same as `search_values`, with each .img searched on a pool of threads. The results are in
+/// the same order.
+pub fn search_values_parallel(node: &ArcWzNode, query: &WzValueQuery) -> Vec<String> {
+    // Directories only hold .img files, so the matches all come from inside one
+    let mut imgs = vec![];
+    collect_imgs(node, "", &mut imgs);
+
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let chunk_size = imgs.len().div_ceil(threads).max(1);
+
+    thread::scope(|scope| {
+        let handles = imgs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut paths = vec![];
+                    for (path, img) in chunk {
+                        if query.matches(&img.value) {
+                            paths.push(path.clone());
+                        }
+                        paths.extend(
+                            search_values(img, query)
+                                .into_iter()
+                                .map(|child_path| join_path(path, &child_path)),
+                        );
+                    }
+                    paths
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|err| std::panic::resume_unwind(err))
+            })
+            .collect()
+    })
+}
+
+fn collect_imgs(node: &ArcWzNode, path: &str, imgs: &mut Vec<(String, ArcWzNode)>) {
+    for (name, child) in &node.children {
+        let child_path = join_path(path, name);
+        match child.value {
+            WzValue::Directory => collect_imgs(child, &child_path, imgs),
+            _ => imgs.push((child_path, child.clone())),
+        }
+    }
+}
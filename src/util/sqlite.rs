@@ -0,0 +1,190 @@
+use crate::{
+    parse_canvas, parse_sound_buffer, parse_sound_header, visit, write_png, ArcWzNode, WzReader,
+    WzValue, WzVisitor, WzWalkControl,
+};
+use rusqlite::{params, types::Value, Connection, Transaction};
+use std::{io::Error, sync::Arc};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS nodes (
+        id INTEGER PRIMARY KEY,
+        parent_id INTEGER REFERENCES nodes(id),
+        path TEXT NOT NULL,
+        name TEXT NOT NULL,
+        type TEXT NOT NULL,
+        value,
+        x INTEGER,
+        y INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS nodes_parent_id ON nodes(parent_id);
+    CREATE INDEX IF NOT EXISTS nodes_name ON nodes(name);
+    CREATE INDEX IF NOT EXISTS nodes_path ON nodes(path);
+    CREATE TABLE IF NOT EXISTS canvases (
+        node_id INTEGER PRIMARY KEY REFERENCES nodes(id),
+        width INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        format INTEGER NOT NULL,
+        origin_x INTEGER NOT NULL,
+        origin_y INTEGER NOT NULL,
+        png BLOB
+    );
+    CREATE TABLE IF NOT EXISTS sounds (
+        node_id INTEGER PRIMARY KEY REFERENCES nodes(id),
+        duration INTEGER NOT NULL,
+        header BLOB,
+        data BLOB
+    );
+";
+
+/// Flatten a tree into a SQLite database with a `nodes` table, plus `canvases` and `sounds`
+/// tables. `value` holds integers, reals or text depending on the node type, so it can be
+/// compared directly, e.g. `WHERE name = 'level' AND value > 100`. Canvas PNGs and sound data
+/// are only stored with a reader. Returns the number of nodes written.
+///
+/// Note that SQLite sorts text after numbers, filter on `type` when mixing both.
+pub fn export_sqlite(
+    node: &ArcWzNode,
+    reader: Option<Arc<WzReader>>,
+    output_file: &str,
+) -> Result<usize, Error> {
+    let mut connection = Connection::open(output_file).map_err(Error::other)?;
+    export_sqlite_to_connection(node, reader, &mut connection)
+}
+
+pub fn export_sqlite_to_connection(
+    node: &ArcWzNode,
+    reader: Option<Arc<WzReader>>,
+    connection: &mut Connection,
+) -> Result<usize, Error> {
+    connection.execute_batch(SCHEMA).map_err(Error::other)?;
+
+    let transaction = connection.transaction().map_err(Error::other)?;
+    let root_id =
+        insert_node(&transaction, node, None, &node.name, reader.as_ref()).map_err(Error::other)?;
+
+    let mut visitor = SqliteVisitor {
+        transaction: &transaction,
+        reader: reader.as_ref(),
+        root_path: &node.name,
+        parent_ids: vec![root_id],
+        count: 1,
+        error: None,
+    };
+    visit(node, &mut visitor);
+    if let Some(err) = visitor.error {
+        return Err(Error::other(err));
+    }
+    let count = visitor.count;
+
+    transaction.commit().map_err(Error::other)?;
+
+    Ok(count)
+}
+
+// Inserts every node when entered, the ids of the nodes still being visited are the parents
+struct SqliteVisitor<'a> {
+    transaction: &'a Transaction<'a>,
+    reader: Option<&'a Arc<WzReader>>,
+    root_path: &'a str,
+    parent_ids: Vec<i64>,
+    count: usize,
+    error: Option<rusqlite::Error>,
+}
+
+impl WzVisitor for SqliteVisitor<'_> {
+    fn enter(&mut self, path: &str, node: &ArcWzNode) -> WzWalkControl {
+        let path = format!("{}/{}", self.root_path, path);
+        let parent_id = self.parent_ids.last().copied();
+        match insert_node(self.transaction, node, parent_id, &path, self.reader) {
+            Ok(id) => {
+                self.parent_ids.push(id);
+                self.count += 1;
+                WzWalkControl::Continue
+            }
+            Err(err) => {
+                self.error = Some(err);
+                WzWalkControl::Stop
+            }
+        }
+    }
+
+    fn leave(&mut self, _path: &str, _node: &ArcWzNode) {
+        self.parent_ids.pop();
+    }
+}
+
+fn insert_node(
+    transaction: &Transaction,
+    node: &ArcWzNode,
+    parent_id: Option<i64>,
+    path: &str,
+    reader: Option<&Arc<WzReader>>,
+) -> rusqlite::Result<i64> {
+    let (value, x, y) = match &node.value {
+        WzValue::Short(val) => (Value::Integer(*val as i64), None, None),
+        WzValue::Int(val) => (Value::Integer(*val as i64), None, None),
+        WzValue::Long(val) => (Value::Integer(*val), None, None),
+        WzValue::Float(val) => (Value::Real(*val as f64), None, None),
+        WzValue::Double(val) => (Value::Real(*val), None, None),
+        WzValue::String(val) | WzValue::Uol(val) => (Value::Text(val.clone()), None, None),
+        WzValue::Vector(val) => (Value::Null, Some(val.x), Some(val.y)),
+        _ => (Value::Null, None, None),
+    };
+
+    transaction
+        .prepare_cached(
+            "INSERT INTO nodes (parent_id, path, name, type, value, x, y)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?
+        .execute(params![
+            parent_id,
+            path,
+            node.name,
+            node.value.type_name(),
+            value,
+            x,
+            y
+        ])?;
+    let id = transaction.last_insert_rowid();
+
+    match &node.value {
+        WzValue::Canvas(canvas) => {
+            let png = reader.and_then(|reader| {
+                let image = parse_canvas(canvas, reader.clone())
+                    .inspect_err(|err| log::warn!("failed to decode canvas {}: {}", path, err))
+                    .ok()?;
+                let mut png = vec![];
+                write_png(&image, &mut png).ok()?;
+                Some(png)
+            });
+
+            transaction
+                .prepare_cached(
+                    "INSERT INTO canvases (node_id, width, height, format, origin_x, origin_y, png)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )?
+                .execute(params![
+                    id,
+                    canvas.width,
+                    canvas.height,
+                    canvas.format1 + canvas.format2 as u32,
+                    canvas.origin.x,
+                    canvas.origin.y,
+                    png
+                ])?;
+        }
+        WzValue::Sound(sound) => {
+            let header = reader.and_then(|reader| parse_sound_header(sound, reader.clone()).ok());
+            let data = reader.and_then(|reader| parse_sound_buffer(sound, reader.clone()).ok());
+
+            transaction
+                .prepare_cached(
+                    "INSERT INTO sounds (node_id, duration, header, data) VALUES (?1, ?2, ?3, ?4)",
+                )?
+                .execute(params![id, sound.duration, header, data])?;
+        }
+        _ => {}
+    }
+
+    Ok(id)
+}
@@ -0,0 +1,121 @@
+use crate::{
+    encode_canvas, write_canvas_payload, ArcWzNode, Vec2, WzCanvasQuality, WzImage, WzNode,
+    WzReader, WzSound, WzValue, WzWaveFormat,
+};
+use indexmap::IndexMap;
+use std::{io::Cursor, sync::Arc};
+
+pub(crate) fn node(name: &str, value: impl Into<WzValue>, children: Vec<ArcWzNode>) -> ArcWzNode {
+    let children = children
+        .into_iter()
+        .map(|child| (child.name.clone(), child))
+        .collect::<IndexMap<_, _>>();
+    Arc::new(WzNode::new_with_children(name, 0, value, children))
+}
+
+pub(crate) fn leaf(name: &str, value: impl Into<WzValue>) -> ArcWzNode {
+    node(name, value, vec![])
+}
+
+pub(crate) fn test_image(width: u32, height: u32) -> WzImage {
+    let data = (0..width * height * 4)
+        .map(|i| {
+            if i % 4 == 3 {
+                255
+            } else {
+                (i * 37 % 251) as u8
+            }
+        })
+        .collect();
+
+    WzImage {
+        width,
+        height,
+        origin: Vec2 { x: 1, y: 2 },
+        data,
+    }
+}
+
+// A Sound_DX8 header holding a PCM wave format, the way the parser reads it
+pub(crate) fn test_sound_header() -> Vec<u8> {
+    let format = WzWaveFormat {
+        format_tag: WzWaveFormat::FORMAT_PCM,
+        channels: 1,
+        samples_per_sec: 8000,
+        avg_bytes_per_sec: 8000,
+        block_align: 1,
+        bits_per_sample: 8,
+        extra: vec![],
+    }
+    .to_bytes();
+    // .wz files keep the extra size of PCM formats too
+    let format = [format, vec![0, 0]].concat();
+
+    let mut header = WzSound::SOUND_HEADER.to_vec();
+    header.push(format.len() as u8);
+    header.extend_from_slice(&format);
+    header
+}
+
+/// A Directory holding an .img with every kind of value, canvas and sound payloads included
+pub(crate) fn test_tree() -> (ArcWzNode, Arc<WzReader>) {
+    let mut payloads = vec![];
+
+    let (mut canvas, data) = encode_canvas(&test_image(5, 3), 2, WzCanvasQuality::Fast).unwrap();
+    canvas.offset = write_canvas_payload(&mut payloads, &data);
+
+    let header = test_sound_header();
+    let buffer = (0..64).collect::<Vec<u8>>();
+    let sound = WzSound {
+        name: "bgm".to_string(),
+        duration: 8,
+        header_offset: payloads.len() as u64,
+        header_size: header.len(),
+        buffer_offset: (payloads.len() + header.len()) as u64,
+        buffer_size: buffer.len(),
+        format: crate::parse_sound_format(&header, None),
+    };
+    payloads.extend_from_slice(&header);
+    payloads.extend_from_slice(&buffer);
+
+    let img = node(
+        "0100100.img",
+        WzValue::Img,
+        vec![
+            leaf("null", WzValue::Null),
+            leaf("short", WzValue::Short(-7)),
+            leaf("int", WzValue::Int(70000)),
+            leaf("long", WzValue::Long(1 << 40)),
+            leaf("float", WzValue::Float(0.5)),
+            leaf("double", WzValue::Double(-2.25)),
+            leaf("string", WzValue::String("a \"b\" <c> & 'd'\n".to_string())),
+            leaf("vector", WzValue::Vector(Vec2 { x: -3, y: 4 })),
+            node(
+                "stand",
+                WzValue::Extended,
+                vec![node(
+                    "0",
+                    WzValue::Canvas(canvas),
+                    vec![
+                        leaf("origin", WzValue::Vector(Vec2 { x: 1, y: 2 })),
+                        leaf("delay", WzValue::Int(120)),
+                    ],
+                )],
+            ),
+            leaf("uol", WzValue::Uol("stand/0".to_string())),
+            node(
+                "foothold",
+                WzValue::Convex,
+                vec![
+                    leaf("0", WzValue::Vector(Vec2 { x: 0, y: 0 })),
+                    leaf("1", WzValue::Vector(Vec2 { x: 10, y: 0 })),
+                ],
+            ),
+            leaf("sound", WzValue::Sound(sound)),
+        ],
+    );
+    let root = node("Mob.wz", WzValue::Directory, vec![img]);
+    let reader = WzReader::new(Cursor::new(payloads), None);
+
+    (root, reader.into())
+}
@@ -9,7 +9,11 @@ pub fn resolve_uol_path(original_path: String, uol_path: String) -> Result<Strin
         .ok_or_else(|| Error::new(std::io::ErrorKind::InvalidInput, "Invalid UOL path"))?;
 
     // Split the original path and backtrack
-    let mut splitted_original_path: Vec<&str> = original_path.split('/').collect();
+    // An empty path is the root, e.g. the .img node
+    let mut splitted_original_path: Vec<&str> = original_path
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .collect();
     if backtrack_len > splitted_original_path.len() {
         return Err(Error::new(
             std::io::ErrorKind::InvalidInput,
@@ -1,16 +1,27 @@
-use crate::{parse_directory, ArcWzNode, WzReader, WzValueCast, WZ_GMS_IV, WZ_GMS_OLD_IV};
+use crate::{parse_directory, WzReader, WzValueCast, WZ_GMS_IV, WZ_GMS_OLD_IV, WZ_KMS_IV};
 use std::{
-    collections::HashMap,
     io::{Error, ErrorKind},
+    ops::Range,
     sync::Arc,
 };
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum WzVersion {
     AUTO_DETECT,
     GMS_OLD,
     GMS,
+    KMS,
+    /// Same key as GMS
+    CMS,
+    /// Same key as GMS
+    TMS,
+    /// Same key as KMS
+    MSEA,
+    /// Same key as GMS
+    JMS,
+    /// A client using a non-standard IV
+    CUSTOM([u8; 4]),
 }
 
 pub fn get_iv_for_version(version: WzVersion) -> [u8; 4] {
@@ -18,12 +29,30 @@ pub fn get_iv_for_version(version: WzVersion) -> [u8; 4] {
         WzVersion::AUTO_DETECT => WZ_GMS_IV,
         WzVersion::GMS => WZ_GMS_IV,
         WzVersion::GMS_OLD => WZ_GMS_OLD_IV,
+        WzVersion::KMS => WZ_KMS_IV,
+        WzVersion::CMS | WzVersion::TMS | WzVersion::JMS => WZ_GMS_IV,
+        WzVersion::MSEA => WZ_KMS_IV,
+        WzVersion::CUSTOM(iv) => iv,
     }
 }
 
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum WzLayout {
+    /// The encrypted version sits at the file start, followed by the root directory
+    #[default]
+    VersionHeader,
+    /// 64-bit clients (v230+) drop the encrypted version, the root directory starts at the file start
+    NoVersionHeader,
+}
+
 pub const INVALID_VERSION: i16 = -1;
 const MAX_BRUTE_FORCE_VERSION: i16 = 230;
 
+// Files without a version header still hash their offsets with a version. 64-bit clients
+// have only been seen using 777, the same 770 to 779 window as MapleLib is searched in case
+// other regions pick a neighbour.
+const NO_VERSION_HEADER_VERSIONS: Range<i16> = 770..780;
+
 // Returns if this version is set to a valid value
 fn is_version_valid(version: i16) -> bool {
     version != INVALID_VERSION
@@ -51,176 +80,347 @@ fn match_version_hash(version: i16, version_hash: u32) -> bool {
 }
 
 // Test the version hash, then set the reader position back to its original position
-fn verify_version_and_version_hash(
+fn verify_version_hash(
     reader: Arc<WzReader>,
-    version: i16,
+    layout: WzLayout,
     version_hash: u32,
 ) -> Result<(), Error> {
     let original_position = reader.get_position()?;
-    let test_result = test_version_and_version_hash(reader.clone(), version, version_hash);
+    let test_result = test_version_hash(reader.clone(), layout, version_hash);
     reader.seek(original_position)?;
     test_result
 }
 
-// Test the version and version hash with a dummy directory
-fn test_version_and_version_hash(
+// Test the version hash with a dummy directory, every offset must land inside the file
+fn test_version_hash(
     reader: Arc<WzReader>,
-    version: i16,
+    layout: WzLayout,
     version_hash: u32,
 ) -> Result<(), Error> {
     // Set the reader's version hash
     reader.set_version_hash(version_hash);
 
-    // Seek to the file offset for this version
-    let file_start = *reader.file_start.borrow();
-    let offset = get_version_offset(file_start as usize, version);
+    // Seek to the root directory for this layout
+    let file_start = *reader.file_start.borrow() as usize;
+    let offset = get_directory_offset(file_start, layout);
     reader.seek(offset as u64)?;
 
     // Test the root directory and look for other directories
     let node = parse_directory(&reader, offset, "Test Directory".to_string(), 0)?;
-    let ref_node = node.as_ref();
-
-    let directories: HashMap<String, ArcWzNode> = ref_node
-        .children
-        .clone()
-        .into_iter()
-        .filter(|(_k, v)| v.value.is_directory() || v.value.is_img())
-        .collect();
 
-    if directories.is_empty() {
-        return Err(Error::new(ErrorKind::Other, "Failed directory test"));
+    if node.children.is_empty() {
+        return Err(Error::other("Failed directory test"));
     }
 
-    let objects: HashMap<String, ArcWzNode> = node
+    let file_len = reader.file.borrow().get_ref().len();
+    if node
         .children
-        .clone()
-        .into_iter()
-        .filter(|(_k, v)| !v.value.is_directory() && !v.value.is_null())
-        .collect();
+        .values()
+        .any(|child| child.offset < file_start || child.offset >= file_len)
+    {
+        return Err(Error::other("Failed offset test"));
+    }
 
     // If there are objects, check to see if it has the .img header
-    if !objects.is_empty() {
-        let object: &Arc<crate::WzNode> = match objects.iter().next() {
-            Some((_, object)) => object,
-            None => {
-                return Err(Error::new(ErrorKind::Other, "Failed to get next object"));
-            }
-        };
-
-        if object.value.is_null() {
-            return Err(Error::new(ErrorKind::Other, "Failed object test"));
-        }
-
+    if let Some(object) = node.children.values().find(|child| child.value.is_img()) {
         reader.seek(object.offset as u64)?;
 
         let test_byte = reader.read_u8()?;
         if test_byte != WzReader::HEADERBYTE_WITHOUT_OFFSET
             && test_byte != WzReader::HEADERBYTE_WITH_OFFSET
         {
-            return Err(Error::new(ErrorKind::Other, "Failed byte test for object"));
+            return Err(Error::other("Failed byte test for object"));
         }
     }
 
     Ok(())
 }
 
-// For versions v230 or higher
-fn detect_known_version(reader: Arc<WzReader>, version: u16) -> Result<bool, Error> {
-    if version > 0xff {
-        return Ok(true);
-    } else if version == 0x80 {
-        let file_start = *reader.file_start.borrow();
-        reader.seek(file_start as u64)?;
+/// Detect whether the root directory is preceded by the encrypted version.
+/// Leaves the reader at the file start.
+pub fn detect_layout(reader: &WzReader) -> Result<WzLayout, Error> {
+    let file_start = *reader.file_start.borrow() as u64;
+    reader.seek(file_start)?;
+    let encrypted_version = reader.read_u16()?;
+    reader.seek(file_start)?;
+
+    // The encrypted version is a single byte, anything larger is the start of a directory:
+    // an entry count followed by an entry type
+    if encrypted_version > 0xff {
+        return Ok(WzLayout::NoVersionHeader);
+    }
+
+    // Or an entry count that does not fit in a byte, with a zero low byte
+    if encrypted_version == 0x80 {
         let property_count = reader.read_wz_int()?;
+        reader.seek(file_start)?;
         if property_count > 0 && (property_count & 0xFF) == 0 && property_count <= 0xFFFF {
-            return Ok(true);
+            return Ok(WzLayout::NoVersionHeader);
         }
     }
 
-    return Ok(false);
+    Ok(WzLayout::VersionHeader)
 }
 
-// Get the version by testing a known version
-fn attempt_known_version(reader: Arc<WzReader>, version: i16) -> Option<(i16, u32)> {
-    let version_hash = calculate_version_hash(version);
-    match verify_version_and_version_hash(reader.clone(), version, version_hash) {
-        Ok(_) => Some((version, version_hash)),
-        Err(err) => {
-            log::trace!("attempt_known_version error: {}", err);
-            None
-        }
-    }
-}
+// Get every version in the range that passes the directory test. When the file has an
+// encrypted version, only the versions matching it are tested.
+fn bruteforce_versions(
+    reader: Arc<WzReader>,
+    layout: WzLayout,
+    versions: Range<i16>,
+    encrypted_version: Option<u16>,
+) -> Vec<(i16, u32)> {
+    let mut candidates = vec![];
 
-// Get the version by testing all versions between 0 and MAX_BRUTE_FORCE_VERSION
-fn bruteforce_version(reader: Arc<WzReader>, version: i16) -> Option<(i16, u32)> {
-    for brute_force_version in 0..MAX_BRUTE_FORCE_VERSION {
+    for brute_force_version in versions {
         let brute_force_version_hash = calculate_version_hash(brute_force_version);
-        if match_version_hash(version, brute_force_version_hash) {
-            match verify_version_and_version_hash(
-                reader.clone(),
-                brute_force_version,
-                brute_force_version_hash,
-            ) {
-                Ok(_) => return Some((brute_force_version, brute_force_version_hash)),
-                Err(err) => {
-                    log::trace!("bruteforce_version error: {}", err);
-                    continue;
-                }
+        if let Some(encrypted_version) = encrypted_version {
+            if !match_version_hash(encrypted_version as i16, brute_force_version_hash) {
+                continue;
+            }
+        }
+
+        match verify_version_hash(reader.clone(), layout, brute_force_version_hash) {
+            Ok(_) => candidates.push((brute_force_version, brute_force_version_hash)),
+            Err(err) => {
+                log::trace!("bruteforce_version error: {}", err);
+                continue;
             }
         }
     }
 
-    None
+    candidates
 }
 
-/// Parse the main directory for a .wz file. Nodes can only be resolved when parsed first.
-pub fn determine_version(reader: Arc<WzReader>) -> Result<(i16, u32), Error> {
-    let mut version: i16 = INVALID_VERSION;
-    let mut version_hash: u32 = 0;
+/// Find the layout and every patch version (and its hash) that passes the directory test,
+/// lowest first. More than one candidate means the detection is ambiguous.
+pub fn determine_version_candidates(
+    reader: Arc<WzReader>,
+) -> Result<(WzLayout, Vec<(i16, u32)>), Error> {
+    let mut layout = detect_layout(&reader)?;
+    let encrypted_version = reader.read_u16()?;
+    log::trace!(
+        "version from header: {}, layout: {:?}",
+        encrypted_version,
+        layout
+    );
 
-    // Determine file version
-    let version_from_header = reader.read_u16()?;
-    log::trace!("version from header: {}", version_from_header);
+    let mut candidates = vec![];
 
-    let cloned_reader = reader.clone();
+    if layout == WzLayout::VersionHeader {
+        // Brute force the patch version
+        candidates = bruteforce_versions(
+            reader.clone(),
+            layout,
+            0..MAX_BRUTE_FORCE_VERSION,
+            Some(encrypted_version),
+        );
 
-    // This is a known version, go ahead and test
-    if detect_known_version(reader, version_from_header)? {
-        const MAPLE_KNOWN_VERSION: i16 = 777;
-        if let Some((attempt_version, attempt_hash)) =
-            attempt_known_version(cloned_reader, MAPLE_KNOWN_VERSION)
-        {
-            version = attempt_version;
-            version_hash = attempt_hash;
-            log::info!("success! patch version is v230 or greater!");
-        }
-    } else {
-        // Brute force the patch version instead
-        if let Some((attempt_version, attempt_hash)) =
-            bruteforce_version(cloned_reader, version_from_header as i16)
-        {
-            version = attempt_version;
-            version_hash = attempt_hash;
-            log::info!("success! patch version is {}", attempt_version);
+        // Fall back to the header-less layout in case the encrypted version was a lookalike
+        if candidates.is_empty() {
+            layout = WzLayout::NoVersionHeader;
         }
     }
 
-    if !is_version_valid(version) {
+    if layout == WzLayout::NoVersionHeader {
+        // No version to match against, so every hash in the range gets the directory test
+        candidates = bruteforce_versions(reader, layout, NO_VERSION_HEADER_VERSIONS, None);
+    }
+
+    match candidates.as_slice() {
+        [] => {}
+        [(version, _)] => log::info!("success! patch version is {} ({:?})", version, layout),
+        _ => log::warn!(
+            "ambiguous patch version, candidates: {:?}",
+            candidates
+                .iter()
+                .map(|(version, _)| version)
+                .collect::<Vec<_>>()
+        ),
+    }
+
+    if candidates.is_empty() {
         Err(Error::new(
             ErrorKind::NotFound,
-            "Unable to determine version",
+            format!(
+                "Unable to determine version, no patch version passed the directory test (header: {})",
+                encrypted_version
+            ),
         ))
     } else {
-        Ok((version, version_hash))
+        Ok((layout, candidates))
     }
 }
 
-// File offset depends on the version
-pub fn get_version_offset(file_start: usize, version: i16) -> usize {
-    if version > MAX_BRUTE_FORCE_VERSION {
-        file_start
-    } else {
-        file_start + 2
+/// Parse the main directory for a .wz file. Nodes can only be resolved when parsed first.
+pub fn determine_version(reader: Arc<WzReader>) -> Result<(i16, u32), Error> {
+    let (_, candidates) = determine_version_candidates(reader)?;
+    Ok(candidates[0])
+}
+
+/// Check an explicit patch version against the file, skipping detection.
+/// Returns the layout and the version hash.
+pub fn verify_patch_version(reader: Arc<WzReader>, version: i16) -> Result<(WzLayout, u32), Error> {
+    if !is_version_valid(version) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid patch version {}", version),
+        ));
+    }
+
+    let layout = detect_layout(&reader)?;
+    let version_hash = calculate_version_hash(version);
+    verify_version_hash(reader, layout, version_hash).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Patch version {} does not match this file: {}",
+                version, err
+            ),
+        )
+    })?;
+
+    Ok((layout, version_hash))
+}
+
+// The root directory follows the encrypted version, if there is one
+pub fn get_directory_offset(file_start: usize, layout: WzLayout) -> usize {
+    match layout {
+        WzLayout::VersionHeader => file_start + 2,
+        WzLayout::NoVersionHeader => file_start,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const FILE_START: u32 = 60;
+    const COPYRIGHT: &str = "Package file v1.0 Copyright 2002 Wizet, ZMS";
+
+    fn encrypt_version(version_hash: u32) -> u16 {
+        let bytes = version_hash.to_be_bytes();
+        (0xFF ^ bytes[0] ^ bytes[1] ^ bytes[2] ^ bytes[3]) as u16
+    }
+
+    // The inverse of `WzReader::read_wz_offset` for an offset stored at `position`
+    fn encrypt_offset(position: usize, version_hash: u32, offset: u32) -> u32 {
+        let mut key = (position as u64 - FILE_START as u64) ^ 0xFFFFFFFF;
+        key *= version_hash as u64;
+        key -= 0x581C3F6D;
+        let key = (key as u32).rotate_left((key & 0x1F) as u32);
+        offset.wrapping_sub(FILE_START * 2) ^ key
+    }
+
+    // A PKG1 archive with a root directory holding a single .img entry
+    fn build_archive(layout: WzLayout, version: i16, entry_type: u8) -> Vec<u8> {
+        let version_hash = calculate_version_hash(version);
+
+        let mut data = b"PKG1".to_vec();
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&FILE_START.to_le_bytes());
+        data.extend_from_slice(COPYRIGHT.as_bytes());
+        data.push(0);
+        assert_eq!(data.len(), FILE_START as usize);
+
+        if layout == WzLayout::VersionHeader {
+            data.extend_from_slice(&encrypt_version(version_hash).to_le_bytes());
+        }
+
+        // Entry count, type and name
+        data.push(1);
+        data.push(entry_type);
+        if entry_type == 3 || entry_type == 4 {
+            let name = "a.img";
+            data.push(-(name.len() as i8) as u8);
+            data.extend(name.bytes().zip(0xAAu8..).map(|(c, mask)| c ^ mask));
+        }
+
+        // Size and checksum, then the offset of the .img right after the directory
+        data.push(16);
+        data.push(0);
+        let position = data.len();
+        let img_offset = position as u32 + 4;
+        data.extend_from_slice(&encrypt_offset(position, version_hash, img_offset).to_le_bytes());
+        data.push(WzReader::HEADERBYTE_WITHOUT_OFFSET);
+        data.extend_from_slice(&[0; 15]);
+
+        let size = (data.len() - FILE_START as usize) as u64;
+        data[4..12].copy_from_slice(&size.to_le_bytes());
+        data
+    }
+
+    fn reader_for(data: Vec<u8>) -> Arc<WzReader> {
+        let reader = WzReader::new(Cursor::new(data), None);
+        reader.set_file_start(FILE_START);
+        reader.into()
+    }
+
+    #[test]
+    fn test_version_header_archive() {
+        let reader = reader_for(build_archive(WzLayout::VersionHeader, 83, 4));
+
+        assert_eq!(detect_layout(&reader).unwrap(), WzLayout::VersionHeader);
+        let (layout, candidates) = determine_version_candidates(reader.clone()).unwrap();
+        assert_eq!(layout, WzLayout::VersionHeader);
+        assert_eq!(candidates, vec![(83, calculate_version_hash(83))]);
+        assert_eq!(
+            get_directory_offset(FILE_START as usize, layout),
+            FILE_START as usize + 2
+        );
+
+        let root = parse_directory(
+            &reader,
+            get_directory_offset(FILE_START as usize, layout),
+            "Root".to_string(),
+            0,
+        )
+        .unwrap();
+        assert!(root.children.contains_key("a.img"));
+    }
+
+    #[test]
+    fn test_no_version_header_archive() {
+        let reader = reader_for(build_archive(WzLayout::NoVersionHeader, 777, 4));
+
+        assert_eq!(detect_layout(&reader).unwrap(), WzLayout::NoVersionHeader);
+        let (layout, candidates) = determine_version_candidates(reader).unwrap();
+        assert_eq!(layout, WzLayout::NoVersionHeader);
+        assert_eq!(candidates, vec![(777, calculate_version_hash(777))]);
+        assert_eq!(
+            get_directory_offset(FILE_START as usize, layout),
+            FILE_START as usize
+        );
+    }
+
+    #[test]
+    fn test_no_version_header_range() {
+        for version in [770, 779] {
+            let reader = reader_for(build_archive(WzLayout::NoVersionHeader, version, 4));
+            let (_, candidates) = determine_version_candidates(reader).unwrap();
+            assert_eq!(candidates[0].0, version);
+        }
+
+        let reader = reader_for(build_archive(WzLayout::NoVersionHeader, 780, 4));
+        assert!(determine_version_candidates(reader).is_err());
+    }
+
+    #[test]
+    fn test_version_header_lookalike() {
+        // An entry type of 0 makes the directory start like an encrypted version
+        let reader = reader_for(build_archive(WzLayout::NoVersionHeader, 777, 0));
+
+        assert_eq!(detect_layout(&reader).unwrap(), WzLayout::VersionHeader);
+        let (layout, candidates) = determine_version_candidates(reader).unwrap();
+        assert_eq!(layout, WzLayout::NoVersionHeader);
+        assert_eq!(candidates[0].0, 777);
+    }
+
+    #[test]
+    fn test_verify_patch_version() {
+        let reader = reader_for(build_archive(WzLayout::VersionHeader, 83, 4));
+        assert!(verify_patch_version(reader.clone(), 83).is_ok());
+        assert!(verify_patch_version(reader, 84).is_err());
     }
 }
@@ -0,0 +1,285 @@
+use crate::{safe_file_name, ArcWzNode, Vec2, WzCanvas, WzNode, WzSound, WzValue, WzValueCast};
+use indexmap::IndexMap;
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{Error, ErrorKind, Result, Write},
+    path::Path,
+    sync::Arc,
+};
+
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+
+/// Export a node, usually a .img, to the classic XML dump format used by server emulators
+pub fn to_xml(node: &ArcWzNode) -> Result<String> {
+    let mut xml = String::from(XML_DECLARATION);
+    xml.push('\n');
+    write_xml_node(&mut xml, node, 0).map_err(Error::other)?;
+    Ok(xml)
+}
+
+pub fn write_xml_to_file(xml: &str, output_file: &str) -> Result<()> {
+    let mut file = File::create(output_file)?;
+    file.write_all(xml.as_bytes())?;
+    Ok(())
+}
+
+/// Write every .img under this node as `<name>.xml`, mirroring the directory structure
+pub fn export_xml(node: &ArcWzNode, output_dir: &Path) -> Result<usize> {
+    if node.value.is_img() {
+        fs::create_dir_all(output_dir)?;
+        let output_file = output_dir.join(format!("{}.xml", safe_file_name(&node.name)));
+        write_xml_to_file(&to_xml(node)?, &output_file.to_string_lossy())?;
+        return Ok(1);
+    }
+
+    let mut count = 0;
+    for child in node.children.values() {
+        if child.value.is_img() {
+            count += export_xml(child, output_dir)?;
+        } else if child.value.is_directory() {
+            count += export_xml(child, &output_dir.join(safe_file_name(&child.name)))?;
+        }
+    }
+
+    Ok(count)
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_xml_node(xml: &mut String, node: &WzNode, depth: usize) -> std::fmt::Result {
+    let indent = "  ".repeat(depth);
+    let name = escape_xml(&node.name);
+
+    let (tag, attributes) = match &node.value {
+        WzValue::Null => ("null", String::new()),
+        WzValue::Directory | WzValue::Img | WzValue::Extended => ("imgdir", String::new()),
+        WzValue::Convex => ("extended", String::new()),
+        WzValue::Short(val) => ("short", format!(r#" value="{}""#, val)),
+        WzValue::Int(val) => ("int", format!(r#" value="{}""#, val)),
+        WzValue::Long(val) => ("long", format!(r#" value="{}""#, val)),
+        WzValue::Float(val) => ("float", format!(r#" value="{:?}""#, val)),
+        WzValue::Double(val) => ("double", format!(r#" value="{:?}""#, val)),
+        WzValue::String(val) => ("string", format!(r#" value="{}""#, escape_xml(val))),
+        WzValue::Vector(val) => ("vector", format!(r#" x="{}" y="{}""#, val.x, val.y)),
+        WzValue::Canvas(canvas) => (
+            "canvas",
+            format!(r#" width="{}" height="{}""#, canvas.width, canvas.height),
+        ),
+        WzValue::Sound(sound) => ("sound", format!(r#" length="{}""#, sound.duration)),
+        WzValue::Uol(val) => ("uol", format!(r#" value="{}""#, escape_xml(val))),
+    };
+
+    if node.children.is_empty() {
+        return writeln!(xml, r#"{}<{} name="{}"{}/>"#, indent, tag, name, attributes);
+    }
+
+    writeln!(xml, r#"{}<{} name="{}"{}>"#, indent, tag, name, attributes)?;
+    for child in node.children.values() {
+        write_xml_node(xml, child, depth + 1)?;
+    }
+    writeln!(xml, "{}</{}>", indent, tag)
+}
+
+/// Parse a classic XML dump back into a tree. Canvases and sounds only keep their metadata.
+pub fn from_xml(xml: &str) -> Result<ArcWzNode> {
+    let document =
+        roxmltree::Document::parse(xml).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let root = document.root_element();
+
+    let mut node = parse_xml_node(&root)?;
+
+    // The top level imgdir is the .img itself
+    if root.tag_name().name() == "imgdir" {
+        node.value = WzValue::Img;
+    }
+
+    Ok(Arc::new(node))
+}
+
+fn get_attribute<'a>(element: &roxmltree::Node<'a, '_>, name: &str) -> Result<&'a str> {
+    element.attribute(name).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Missing attribute '{}' on <{}> at {}",
+                name,
+                element.tag_name().name(),
+                element.range().start
+            ),
+        )
+    })
+}
+
+fn parse_attribute<T: std::str::FromStr>(element: &roxmltree::Node, name: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = get_attribute(element, name)?;
+    value.parse::<T>().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid attribute {}=\"{}\": {}", name, value, e),
+        )
+    })
+}
+
+fn parse_xml_node(element: &roxmltree::Node) -> Result<WzNode> {
+    let name = get_attribute(element, "name")?.to_string();
+
+    let mut children = IndexMap::new();
+    for child in element.children().filter(|child| child.is_element()) {
+        let child_node = parse_xml_node(&child)?;
+        children.insert(child_node.name.clone(), Arc::new(child_node));
+    }
+
+    let value = match element.tag_name().name() {
+        "null" => WzValue::Null,
+        "imgdir" => WzValue::Extended,
+        "extended" => WzValue::Convex,
+        "short" => WzValue::Short(parse_attribute(element, "value")?),
+        "int" => WzValue::Int(parse_attribute(element, "value")?),
+        "long" => WzValue::Long(parse_attribute(element, "value")?),
+        "float" => WzValue::Float(parse_attribute(element, "value")?),
+        "double" => WzValue::Double(parse_attribute(element, "value")?),
+        "string" => WzValue::String(get_attribute(element, "value")?.to_string()),
+        "uol" => WzValue::Uol(get_attribute(element, "value")?.to_string()),
+        "vector" => WzValue::Vector(Vec2 {
+            x: parse_attribute(element, "x")?,
+            y: parse_attribute(element, "y")?,
+        }),
+        "canvas" => {
+            // Same as the parser, the origin comes from the children
+            let origin = children
+                .get("origin")
+                .and_then(|origin| origin.value.as_vector())
+                .cloned()
+                .unwrap_or_default();
+
+            WzValue::Canvas(WzCanvas {
+                width: parse_attribute(element, "width")?,
+                height: parse_attribute(element, "height")?,
+                origin,
+                ..Default::default()
+            })
+        }
+        "sound" => WzValue::Sound(WzSound {
+            name: name.clone(),
+            duration: element
+                .attribute("length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or_default(),
+            ..Default::default()
+        }),
+        tag => Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Unsupported xml element: {} {}", name, tag),
+        ))?,
+    };
+
+    Ok(WzNode::new_with_children(&name, 0, value, children))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diff_nodes,
+        test_tree::{leaf, node, test_tree},
+    };
+
+    #[test]
+    fn test_escaping() {
+        let img = node(
+            "a&b.img",
+            WzValue::Img,
+            vec![leaf(
+                "<\"quoted\">",
+                WzValue::String("'x' & \"y\"\r\n<z>".to_string()),
+            )],
+        );
+
+        let xml = to_xml(&img).unwrap();
+        assert!(xml.contains(r#"<imgdir name="a&amp;b.img">"#));
+        assert!(xml.contains(
+            r#"<string name="&lt;&quot;quoted&quot;&gt;" value="&apos;x&apos; &amp; &quot;y&quot;&#13;&#10;&lt;z&gt;"/>"#
+        ));
+
+        let parsed = from_xml(&xml).unwrap();
+        assert!(diff_nodes(&img, None, &parsed, None).is_empty());
+    }
+
+    #[test]
+    fn test_element_types() {
+        let (root, _) = test_tree();
+        let xml = to_xml(&root.children["0100100.img"]).unwrap();
+
+        for element in [
+            r#"<imgdir name="0100100.img">"#,
+            r#"<null name="null"/>"#,
+            r#"<short name="short" value="-7"/>"#,
+            r#"<int name="int" value="70000"/>"#,
+            r#"<long name="long" value="1099511627776"/>"#,
+            r#"<float name="float" value="0.5"/>"#,
+            r#"<double name="double" value="-2.25"/>"#,
+            r#"<vector name="vector" x="-3" y="4"/>"#,
+            r#"<imgdir name="stand">"#,
+            r#"<canvas name="0" width="5" height="3">"#,
+            r#"<uol name="uol" value="stand/0"/>"#,
+            r#"<extended name="foothold">"#,
+            r#"<sound name="sound" length="8"/>"#,
+        ] {
+            assert!(xml.contains(element), "missing {} in {}", element, xml);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (root, _) = test_tree();
+        let img = &root.children["0100100.img"];
+        let parsed = from_xml(&to_xml(img).unwrap()).unwrap();
+
+        // Only the canvas format and the sound size are not part of the dump
+        let paths = diff_nodes(img, None, &parsed, None)
+            .entries
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["stand/0", "sound"]);
+
+        let WzValue::Canvas(canvas) = &parsed.children["stand"].children["0"].value else {
+            panic!("expected a canvas");
+        };
+        assert_eq!((canvas.origin.x, canvas.origin.y), (1, 2));
+    }
+
+    #[test]
+    fn test_export_safe_file_names() {
+        let output_dir = std::env::temp_dir().join(format!("wz-xml-test-{}", std::process::id()));
+        let img = node("a:b.img", WzValue::Img, vec![]);
+        let root = node(
+            "Map.wz",
+            WzValue::Directory,
+            vec![node("..", WzValue::Directory, vec![img])],
+        );
+
+        assert_eq!(export_xml(&root, &output_dir).unwrap(), 1);
+        assert!(output_dir.join("__").join("a_b.img.xml").is_file());
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
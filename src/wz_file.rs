@@ -1,20 +1,37 @@
 use crate::{
-    crypto::generate_wz_key, determine_version, get_iv_for_version, get_version_offset,
-    parse_directory, parse_wz_header, ArcWzNode, WzReader, WzVersion, INVALID_VERSION,
+    crypto::{
+        generate_wz_key_with_user_key, rank_wz_versions, WzMutableKey,
+        MAPLESTORY_AES_USERKEY_DEFAULT, WZ_KNOWN_VERSIONS,
+    },
+    determine_version_candidates, get_directory_offset, get_iv_for_version, parse_directory,
+    parse_wz_header, read_encrypted_version, verify_patch_version, ArcWzNode, WzHeader, WzLayout,
+    WzReader, WzVersion, INVALID_VERSION,
 };
 use std::{
     fs::{self, File},
-    io::{Cursor, Error, Read},
+    io::{Cursor, Error, ErrorKind, Read},
     path::PathBuf,
     sync::Arc,
 };
 
 pub struct WzFile {
+    pub aes_user_key: [u8; 128],
+    pub custom_ivs: Vec<[u8; 4]>,
+    /// The key that decoded this file once opened, `CUSTOM` for registered IVs
+    pub detected_version: Option<WzVersion>,
     pub file_path: PathBuf,
     pub file_version: WzVersion,
+    pub header: WzHeader,
+    /// Whether the declared size in the header matches the file length
+    pub header_size_valid: bool,
+    pub layout: WzLayout,
     pub name: String,
+    /// Explicit patch version, skips the version detection when set
+    pub patch_version: Option<i16>,
     pub reader: Arc<WzReader>,
     pub version: i16,
+    /// Every patch version that passed the directory test, more than one if detection was ambiguous
+    pub version_candidates: Vec<i16>,
     pub version_hash: u32,
 }
 
@@ -29,15 +46,38 @@ impl WzFile {
             .into();
 
         Ok(WzFile {
+            aes_user_key: MAPLESTORY_AES_USERKEY_DEFAULT,
+            custom_ivs: vec![],
+            detected_version: None,
             file_path,
             file_version: version,
+            header: WzHeader::default(),
+            header_size_valid: false,
+            layout: WzLayout::default(),
             name,
+            patch_version: None,
             reader: Arc::default(),
             version: INVALID_VERSION,
+            version_candidates: vec![],
             version_hash: 0,
         })
     }
 
+    /// Use a custom 128-byte AES user key instead of the default one. Must be set before `open`.
+    pub fn set_aes_user_key(&mut self, aes_user_key: [u8; 128]) {
+        self.aes_user_key = aes_user_key;
+    }
+
+    /// Add an IV to try when auto-detecting, on top of the known ones. Must be called before `open`.
+    pub fn register_iv(&mut self, iv: [u8; 4]) {
+        self.custom_ivs.push(iv);
+    }
+
+    /// Use a known patch version instead of detecting it. Must be set before `open`.
+    pub fn set_patch_version(&mut self, patch_version: i16) {
+        self.patch_version = Some(patch_version);
+    }
+
     pub fn open(&mut self) -> Result<(), Error> {
         let file_path = &self.file_path;
         let mut file = File::open(file_path)?;
@@ -45,22 +85,32 @@ impl WzFile {
         let mut buffer = vec![0; metadata.len() as usize];
         file.read_exact(&mut buffer)?;
 
-        let mut reader = WzReader::new(
-            Cursor::new(buffer),
-            generate_wz_key(get_iv_for_version(self.file_version)),
-        );
+        // Shared from the start, so trying a key does not copy the whole file
+        let mut reader: Arc<WzReader> =
+            WzReader::new(Cursor::new(buffer), self.generate_key(self.file_version)).into();
 
-        reader.file_start = parse_wz_header(&reader)?.into();
+        let header = parse_wz_header(&reader)?;
+        // Files with a wrong size often still parse, so this is only reported
+        self.header_size_valid = match header.validate_size(metadata.len()) {
+            Ok(()) => true,
+            Err(err) => {
+                log::warn!("{}: {}", self.name, err);
+                false
+            }
+        };
+        reader.set_file_start(header.file_start);
+        self.header = header;
 
-        self.determine_and_set_version(&mut reader);
+        self.determine_and_set_version(&mut reader)?;
+        self.header.encrypted_version = read_encrypted_version(&reader, self.layout)?;
 
-        self.reader = reader.into();
+        self.reader = reader;
 
         Ok(())
     }
 
     pub fn parse_root_directory(&mut self) -> Result<ArcWzNode, Error> {
-        let offset = get_version_offset(*self.reader.file_start.borrow() as usize, self.version);
+        let offset = get_directory_offset(*self.reader.file_start.borrow() as usize, self.layout);
         let level = 99;
 
         let node = parse_directory(&self.reader.clone(), offset, self.name.clone(), level)?;
@@ -68,27 +118,70 @@ impl WzFile {
         Ok(node)
     }
 
-    fn determine_and_set_version(&mut self, reader: &mut WzReader) {
-        let mut try_set_version = |wz_version| {
-            reader.set_wz_mutable_key(generate_wz_key(get_iv_for_version(wz_version)));
-            if let Ok((version, version_hash)) = determine_version(reader.clone().into()) {
-                self.version = version;
-                self.version_hash = version_hash;
-                reader.set_version_hash(version_hash);
-                return true;
+    fn generate_key(&self, wz_version: WzVersion) -> Option<WzMutableKey> {
+        generate_wz_key_with_user_key(get_iv_for_version(wz_version), self.aes_user_key)
+    }
+
+    fn determine_and_set_version(&mut self, reader: &mut Arc<WzReader>) -> Result<(), Error> {
+        let candidates = if self.file_version == WzVersion::AUTO_DETECT {
+            let mut candidates = WZ_KNOWN_VERSIONS.to_vec();
+            candidates.extend(self.custom_ivs.iter().map(|iv| WzVersion::CUSTOM(*iv)));
+
+            // Try the most plausible keys first
+            match rank_wz_versions(get_reader_mut(reader)?, &candidates, self.aes_user_key) {
+                Ok(ranked) => ranked
+                    .into_iter()
+                    .map(|(wz_version, _)| wz_version)
+                    .collect(),
+                Err(err) => {
+                    log::warn!("key detection failed: {}", err);
+                    candidates
+                }
             }
-            false
+        } else {
+            vec![self.file_version]
         };
 
-        // Try to determine version with the current or auto-detected IV
-        if self.file_version == WzVersion::AUTO_DETECT {
-            if try_set_version(self.file_version) {
-                return;
+        let mut errors = vec![];
+        for wz_version in candidates {
+            get_reader_mut(reader)?.set_wz_mutable_key(self.generate_key(wz_version));
+
+            let result = match self.patch_version {
+                Some(patch_version) => verify_patch_version(reader.clone(), patch_version)
+                    .map(|(layout, version_hash)| (layout, vec![(patch_version, version_hash)])),
+                None => determine_version_candidates(reader.clone()),
+            };
+
+            match result {
+                Ok((layout, version_candidates)) => {
+                    let (version, version_hash) = version_candidates[0];
+                    self.version = version;
+                    self.version_hash = version_hash;
+                    self.version_candidates = version_candidates
+                        .into_iter()
+                        .map(|(version, _)| version)
+                        .collect();
+                    self.detected_version = Some(wz_version);
+                    self.layout = layout;
+                    reader.set_version_hash(version_hash);
+                    return Ok(());
+                }
+                Err(err) => errors.push(format!("{:?}: {}", wz_version, err)),
             }
-            // If auto-detect fails, try with GMS_OLD's IV
-            try_set_version(WzVersion::GMS_OLD);
-        } else {
-            try_set_version(self.file_version);
         }
+
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "Unable to determine the version of {} ({})",
+                self.name,
+                errors.join("; ")
+            ),
+        ))
     }
 }
+
+// The version checks only borrow the reader, so it is not shared while the key changes
+fn get_reader_mut(reader: &mut Arc<WzReader>) -> Result<&mut WzReader, Error> {
+    Arc::get_mut(reader).ok_or_else(|| Error::other("The reader is shared while opening"))
+}